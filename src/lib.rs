@@ -6,7 +6,8 @@ use std::collections::HashMap;
 use std::fmt::write;
 use std::fs::File;
 use std::hash::Hash;
-use std::path::{ self, Path };
+use std::path::{ self, Path, PathBuf };
+use std::sync::Arc;
 use std::time::{ Duration, Instant };
 use std::{ fs, io, thread, vec };
 use std::{ io::Write, net::* };
@@ -22,15 +23,83 @@ pub mod src;
 use src::request::Request;
 use src::response::Response;
 use src::*;
+use src::cache::StaticCache;
+use src::parser::{ self, HeaderLimits };
+use src::multipart::MultipartLimits;
+#[cfg(feature = "secure-cookies")]
+use src::cookies::CookieKey;
+use src::auth::Auth;
+use src::connections::{ reject_connection, ConnectionLimits, ServerStats };
+use src::cors::Cors;
+use src::proxy::{ read_proxy_header, resolve_forwarded, IpNet };
+use src::rate_limit::{ RateLimit, RateLimiter };
+use src::session::{ Session, SessionConfig, SessionStore };
+use src::utils::deadline::{ is_timeout, DeadlineReader };
+use src::static_files::{ EmbeddedAssets, StaticOptions };
+use src::utils::utils::matches_prefix;
 
 pub struct Server<T: Clone + std::marker::Send + 'static> {
     active: bool,
     pub max_content_length: usize,
+    /// Max size of a body sent with Content-Encoding (gzip, deflate) after decoding it in bytes (413 if over, 0 for no limit)
+    pub max_decompressed_length: usize,
+    /// Max length of the request line in bytes (414 if over)
+    pub max_request_line: usize,
+    /// Max length of a single header in bytes (431 if over)
+    pub max_header_size: usize,
+    /// Max length of all headers together in bytes (431 if over)
+    pub max_headers_size: usize,
+    /// Max number of headers (431 if over)
+    pub max_header_count: usize,
+    /// Max time to wait for the first byte of a request before closing the connection
+    pub idle_timeout: Option<Duration>,
+    /// Max time to read the request line and headers (408 if over)
+    pub header_timeout: Option<Duration>,
+    /// Max time to read the body (408 if over)
+    pub body_timeout: Option<Duration>,
+    /// Max time a single write to the client can block
+    pub write_timeout: Option<Duration>,
+    /// Max connections handled or waiting for a thread at once (0 for no limit)
+    pub max_connections: usize,
+    /// Max connections waiting for a free thread (0 for no limit)
+    pub max_queue: usize,
+    /// Connections over `max_connections` or `max_queue` get 503 with this Retry-After (closed if None)
+    pub overload_retry_after: Option<Duration>,
+    /// If true, connections from trusted proxies must start with a PROXY protocol (v1 or v2) header
+    pub proxy_protocol: bool,
+    /// Max number of parts in a multipart body (413 if over, 0 for no limit)
+    pub max_multipart_parts: usize,
+    /// Max size of a multipart part that is not a file in bytes (413 if over, 0 for no limit)
+    pub max_part_size: usize,
+    /// Max size of an uploaded file in bytes (413 if over, 0 for no limit)
+    pub max_file_size: u64,
+    /// Uploaded files bigger than this are written to temporary files instead of memory
+    pub spool_threshold: Option<usize>,
+    /// Folder for the temporary upload files (the system temp folder if None)
+    pub spool_dir: Option<PathBuf>,
     pub endpoints: Vec<EndPoint<T>>,
-    pub static_endpoints: HashMap<(String, bool), String>, // Path, Is chunked  -  Folder
+    pub static_endpoints: Vec<StaticEndpoint>,
+    /// If false, static files that resolve (through symlinks) outside of their folder are not served.
+    pub follow_symlinks: bool,
+    /// Content types by extension (lowercase, without the dot). They take priority over the built in table.
+    pub mime_types: HashMap<String, ContentType>,
+    /// If true, static files with unknown extension get their content type guessed from the first bytes.
+    pub sniff_content_type: bool,
+    /// If true, text like static files (html, css, js, json, svg...) are gzip compressed when the client accepts it.
+    pub compress_static: bool,
 
     pub public_var: Option<T>,
 
+    static_cache: Option<Arc<StaticCache>>,
+    #[cfg(feature = "secure-cookies")]
+    cookie_key: Option<Arc<CookieKey>>,
+    sessions: Option<(Arc<dyn SessionStore>, Arc<SessionConfig>)>,
+    cors: Option<Arc<Cors>>,
+    rate_limits: Vec<(String, RateLimit)>,
+    stats: Arc<ServerStats>,
+    trusted_proxies: Vec<IpNet>,
+    auth: Vec<(String, Auth)>,
+
     middleware: Option<
         fn(url: &Url, req: &Request, res: &mut Response, public_var: &Option<T>) -> bool
     >,
@@ -45,9 +114,40 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
         return Server {
             active: false,
             max_content_length: max_content_length.unwrap_or_default(),
+            max_decompressed_length: 32 * 1024 * 1024,
+            max_request_line: HeaderLimits::default().max_request_line,
+            max_header_size: HeaderLimits::default().max_header_size,
+            max_headers_size: HeaderLimits::default().max_headers_size,
+            max_header_count: HeaderLimits::default().max_header_count,
+            idle_timeout: Some(Duration::from_secs(15)),
+            header_timeout: Some(Duration::from_secs(10)),
+            body_timeout: Some(Duration::from_secs(60)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_connections: 0,
+            max_queue: 1024,
+            overload_retry_after: Some(Duration::from_secs(1)),
+            proxy_protocol: false,
+            max_multipart_parts: MultipartLimits::default().max_parts,
+            max_part_size: MultipartLimits::default().max_part_size,
+            max_file_size: MultipartLimits::default().max_file_size,
+            spool_threshold: None,
+            spool_dir: None,
             endpoints: Vec::new(),
-            static_endpoints: HashMap::new(),
+            static_endpoints: Vec::new(),
+            follow_symlinks: true,
+            mime_types: HashMap::new(),
+            sniff_content_type: false,
+            compress_static: false,
             public_var: public_var,
+            static_cache: None,
+            #[cfg(feature = "secure-cookies")]
+            cookie_key: None,
+            sessions: None,
+            cors: None,
+            rate_limits: Vec::new(),
+            stats: Arc::new(ServerStats::default()),
+            trusted_proxies: Vec::new(),
+            auth: Vec::new(),
             middleware: None,
             logger: None,
         };
@@ -63,6 +163,56 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
     pub fn use_logger(&mut self, handle: fn(input: &HttpServerError)) {
         self.logger = Some(handle);
     }
+    ///Sets the content type for files with the given extension (for example "mjs" or ".mjs")
+    pub fn set_mime_type(&mut self, extension: &str, content_type: ContentType) {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        self.mime_types.insert(extension, content_type);
+    }
+    ///Keeps small, frequently requested static files (and their gzip version) in memory.
+    /// Returns a handle to the cache so it can be invalidated (for example from a file watcher).
+    pub fn use_static_cache(&mut self, cache: StaticCache) -> Arc<StaticCache> {
+        let cache = Arc::new(cache);
+        self.static_cache = Some(cache.clone());
+        cache
+    }
+    ///Sets the key for signed and private cookies (`req.signed_cookie`, `res.set_signed_cookie`...)
+    #[cfg(feature = "secure-cookies")]
+    pub fn use_cookie_key(&mut self, key: CookieKey) {
+        self.cookie_key = Some(Arc::new(key));
+    }
+    ///Enables sessions (`req.session`) kept in the given store, for example `MemoryStore` or `FileStore`
+    pub fn use_sessions(&mut self, store: impl SessionStore + 'static, config: SessionConfig) {
+        self.sessions = Some((Arc::new(store), Arc::new(config)));
+    }
+    ///Adds CORS headers to responses and answers preflight (OPTIONS) requests
+    pub fn use_cors(&mut self, cors: Cors) {
+        self.cors = Some(Arc::new(cors));
+    }
+    ///Limits how many requests a client (ip by default) can make to paths under `prefix` ("/" for all).
+    /// Clients over the limit get 429 Too Many Requests. The longest matching prefix is used.
+    pub fn use_rate_limit(&mut self, prefix: &str, limit: RateLimit) {
+        self.rate_limits.retain(|(existing, _)| existing != prefix);
+        self.rate_limits.push((prefix.to_owned(), limit));
+    }
+    ///Requires authentication for paths under `prefix` ("/" for all), the longest matching prefix is used.
+    /// The principal is in `req.principal`, requests without valid credentials get 401.
+    pub fn use_auth(&mut self, prefix: &str, auth: Auth) {
+        self.auth.retain(|(existing, _)| existing != prefix);
+        self.auth.push((prefix.to_owned(), auth));
+        self.auth.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+    }
+    ///Trusts the proxies in these ip ranges ("10.0.0.0/8", "127.0.0.1", "fd00::/8") to tell the client
+    /// ip, scheme and host (`Forwarded` or `X-Forwarded-For`, `X-Forwarded-Proto`, `X-Forwarded-Host`)
+    pub fn use_trusted_proxies(&mut self, ranges: &[&str]) -> Result<(), HttpServerError> {
+        for range in ranges {
+            self.trusted_proxies.push(range.parse::<IpNet>()?);
+        }
+        Ok(())
+    }
+    ///Connection counters (accepted, rejected, queued, active, completed) for monitoring
+    pub fn stats(&self) -> Arc<ServerStats> {
+        self.stats.clone()
+    }
     ///Creates a new static url
     /// For example a folder named "images" on path /images every image in that folder will be exposed like "/images/example.png"
     /// And chunked specifies if it will use chunked transport encoding
@@ -82,19 +232,45 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
                 HttpServerError::new("Folder does not exist or the path provided is a file!")
             );
         }
+        let root = path_.canonicalize().map_err(|e| HttpServerError::new(&e.to_string()))?;
         let mut path = path.to_owned();
         if
             (self.endpoints.len() > 0 &&
                 self.endpoints.iter().any(|x| x.path == path && x.req_type == RequestType::Get)) ||
             (self.static_endpoints.len() > 0 &&
-                self.static_endpoints.iter().any(|x| &x.0.0 == &path))
+                self.static_endpoints.iter().any(|x| &x.path == &path))
         {
             return Err(HttpServerError::new("Endpoint already exists!"));
         }
         if path.len() > 1 && path.ends_with("/") {
             path.remove(path.len() - 1);
         }
-        self.static_endpoints.insert((path, chunked), folder.to_owned());
+        self.static_endpoints.push(
+            StaticEndpoint::new(path, StaticSource::Folder(root), chunked)
+        );
+        Ok(())
+    }
+    ///Creates a new static url serving files compiled into the binary (see `embed_assets!`).
+    /// They get the same content type, ETag, range and compression handling as folders.
+    pub fn new_embedded(&mut self, path: &str, assets: EmbeddedAssets) -> Result<(), HttpServerError> {
+        if self.active == true {
+            return Err(HttpServerError::new("Server is already running!"));
+        }
+        let mut path = path.to_owned();
+        if
+            (self.endpoints.len() > 0 &&
+                self.endpoints.iter().any(|x| x.path == path && x.req_type == RequestType::Get)) ||
+            (self.static_endpoints.len() > 0 &&
+                self.static_endpoints.iter().any(|x| &x.path == &path))
+        {
+            return Err(HttpServerError::new("Endpoint already exists!"));
+        }
+        if path.len() > 1 && path.ends_with("/") {
+            path.remove(path.len() - 1);
+        }
+        self.static_endpoints.push(
+            StaticEndpoint::new(path, StaticSource::Embedded(Arc::new(assets)), false)
+        );
         Ok(())
     }
     fn new_endpoint(
@@ -115,7 +291,7 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
             (self.endpoints.len() > 0 &&
                 self.endpoints.iter().any(|x| x.path == path && x.req_type == req_type)) ||
             (self.static_endpoints.len() > 0 &&
-                self.static_endpoints.iter().any(|x| &x.0.0 == &path))
+                self.static_endpoints.iter().any(|x| &x.path == &path))
         {
             return Err(HttpServerError::new("Endpoint already exists!"));
         }
@@ -209,38 +385,86 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
 
         let pool: ThreadPool = ThreadPool::new(threads.unwrap_or(num_cpus::get()));
         let mut routes = self.endpoints.clone();
-        let static_routes = self.static_endpoints.clone();
+        let mut static_routes = self.static_endpoints.clone();
 
-        let middleware = self.middleware.clone();
         let logger = self.logger.unwrap_or(logger::eprint);
-
-        let max_content_length = self.max_content_length.clone();
         let public_var = self.public_var.clone();
 
         let address = address.unwrap_or("0.0.0.0").to_owned();
 
         order_routes(&mut routes); // Order them so the first one are without params
+        static_routes.sort_by(|a, b| b.path.len().cmp(&a.path.len())); // Longest mount path first
+
+        let context = Arc::new(Context {
+            max_content_length: self.max_content_length,
+            max_decompressed_length: self.max_decompressed_length,
+            header_limits: HeaderLimits {
+                max_request_line: self.max_request_line,
+                max_header_size: self.max_header_size,
+                max_headers_size: self.max_headers_size,
+                max_header_count: self.max_header_count,
+            },
+            idle_timeout: self.idle_timeout,
+            header_timeout: self.header_timeout,
+            body_timeout: self.body_timeout,
+            write_timeout: self.write_timeout,
+            multipart_limits: MultipartLimits {
+                max_parts: self.max_multipart_parts,
+                max_part_size: self.max_part_size,
+                max_file_size: self.max_file_size,
+                spool_threshold: self.spool_threshold,
+                spool_dir: self.spool_dir.clone(),
+            },
+            routes: routes,
+            static_routes: static_routes,
+            static_options: StaticOptions {
+                follow_symlinks: self.follow_symlinks,
+                mime_types: self.mime_types.clone(),
+                sniff_content_type: self.sniff_content_type,
+                compress: self.compress_static,
+                cache: self.static_cache.clone(),
+            },
+            middleware: self.middleware,
+            #[cfg(feature = "secure-cookies")]
+            cookie_key: self.cookie_key.clone(),
+            sessions: self.sessions.clone(),
+            cors: self.cors.clone(),
+            rate_limiter: RateLimiter::new(self.rate_limits.clone()),
+            trusted_proxies: self.trusted_proxies.clone(),
+            proxy_protocol: self.proxy_protocol,
+            auth: self.auth.clone(),
+        });
+
+        let stats = self.stats.clone();
+        let connection_limits = ConnectionLimits {
+            max_connections: self.max_connections,
+            max_queue: self.max_queue,
+            retry_after: self.overload_retry_after,
+        };
 
         thread::spawn(move || {
             let tcp: TcpListener = TcpListener::bind(format!("{}:{}", address, port)).unwrap();
 
             for stream in tcp.incoming() {
-                let routes_clone = routes.clone();
-                let static_routes_clone = static_routes.clone();
-                let max_content_length_clone = max_content_length.clone();
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        logger(&HttpServerError::new(&format!("Failed to accept: {}", e)));
+                        continue;
+                    }
+                };
+                // Full, answered here instead of waiting in the pool queue
+                if !stats.try_queue(&connection_limits) {
+                    reject_connection(stream, &connection_limits);
+                    continue;
+                }
+                let context_clone = context.clone();
                 let public_var_clone = public_var.clone();
+                let stats_clone = stats.clone();
 
                 pool.execute(move || {
-                    let stream = stream.unwrap();
-                    let res = Self::handle_request(
-                        stream,
-                        max_content_length_clone,
-                        routes_clone,
-                        static_routes_clone,
-                        middleware,
-
-                        public_var_clone
-                    );
+                    let _active = stats_clone.start();
+                    let res = Self::handle_request(stream, &context_clone, public_var_clone);
                     if res.is_err() {
                         logger(&res.unwrap_err());
                     }
@@ -254,54 +478,129 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
     }
     fn handle_request(
         stream: TcpStream,
-        max_content_length: usize,
-        routes: Vec<EndPoint<T>>,
-        static_routes: HashMap<(String, bool), String>,
-        middleware: Option<
-            fn(url: &Url, req: &Request, res: &mut Response, public_var: &Option<T>) -> bool
-        >,
+        context: &Context<T>,
         public_var: Option<T>
     ) -> Result<(), HttpServerError> {
         let bump = Bump::new(); // Allocator
 
         let mut bfreader: BufReader<TcpStream> = BufReader::new(
-            stream.try_clone().expect("Failed to create buffer reader")
+            stream
+                .try_clone()
+                .map_err(|e|
+                    HttpServerError::new(&format!("Failed to create buffer reader: {}", e))
+                )?
         );
 
-        let mut headers_string: String = "".to_string();
+        stream
+            .set_write_timeout(context.write_timeout)
+            .map_err(|e| HttpServerError::new(&format!("Failed to set write timeout: {}", e)))?;
+
+        // Wait for the request to start
+        stream
+            .set_read_timeout(context.idle_timeout)
+            .map_err(|e| HttpServerError::new(&format!("Failed to set read timeout: {}", e)))?;
+        match bfreader.fill_buf() {
+            Ok(buffer) if buffer.is_empty() => {
+                return Err(HttpServerError::new("Connection closed without a request!"));
+            }
+            Ok(_) => {}
+            Err(e) if is_timeout(&e) => {
+                return Err(HttpServerError::new("Idle connection timed out!"));
+            }
+            Err(e) => {
+                return Err(HttpServerError::new(&format!("Error reading request: {}", e)));
+            }
+        }
 
-        let mut line = "".to_owned();
-        loop {
-            match bfreader.read_line(&mut line) {
-                Ok(size) => {
-                    if size <= 2 {
-                        break;
+        // A trusted proxy using the PROXY protocol sends the client address first
+        let mut peer = stream.peer_addr().ok();
+        if let Some(proxy) = peer.filter(|_| context.proxy_protocol) {
+            if context.trusted_proxies.iter().any(|net| net.contains(&proxy.ip())) {
+                match
+                    read_proxy_header(&mut DeadlineReader::new(&mut bfreader, context.header_timeout))
+                {
+                    Ok(Some(client)) => {
+                        peer = Some(client);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        return Self::reject(&stream, err);
                     }
-                }
-                Err(e) => {
-                    return Err(HttpServerError::new("Error reading request headers!"));
                 }
             }
-
-            headers_string.push_str(&line);
-
-            line = "".to_string();
         }
 
-        let lines: Vec<&str> = headers_string.lines().collect();
-
-        if lines.len() == 0 {
-            return Err(HttpServerError::new("No headers!"));
+        let head = match
+            parser::read_head(
+                &mut DeadlineReader::new(&mut bfreader, context.header_timeout),
+                &context.header_limits
+            )
+        {
+            Ok(head) => head,
+            Err(err) => {
+                return Self::reject(&stream, err);
+            }
+        };
+        let lines: Vec<&str> = head
+            .iter()
+            .map(|line| line.as_str())
+            .collect();
+
+        let req_url = match Url::parse(lines[0]) {
+            Ok(req_url) => req_url,
+            Err(err) => {
+                return Self::reject(&stream, Self::bad_request(err));
+            }
+        };
+        if let HttpVersion::Other(major, _) = req_url.version {
+            // HTTP/1.x with a higher minor version is handled as HTTP/1.1
+            if major != 1 {
+                return Self::reject(
+                    &stream,
+                    HttpServerError::with_code(
+                        ResponseCode::HttpVersionNotSupported,
+                        &format!("Unsupported version {}!", req_url.version.to_string())
+                    )
+                );
+            }
         }
-        let req_url = Url::parse(lines[0]).unwrap();
 
-        let mut req = Request::parse(&lines, Some(req_url.query), None)?;
+        let mut req = match Request::parse(&lines[1..].to_vec(), None, None) {
+            Ok(req) => req,
+            Err(err) => {
+                return Self::reject(&stream, Self::bad_request(err));
+            }
+        };
 
-        if let Some(socket) = stream.peer_addr().ok() {
-            req.ip = Some(socket.ip().to_string());
+        req.host = req.headers.get("Host").map(|host| host.to_owned());
+        if let Some(peer) = peer {
+            let forwarded = resolve_forwarded(peer.ip(), &req.headers, &context.trusted_proxies);
+            req.ip = forwarded.ip.or(Some(peer.ip().to_string()));
+            req.scheme = forwarded.scheme.unwrap_or(req.scheme);
+            req.host = forwarded.host.or(req.host);
         }
+        req.body_timeout = context.body_timeout;
+        req.multipart_limits = context.multipart_limits.clone();
+        req.max_decompressed_length = context.max_decompressed_length;
+        req.version = req_url.version;
+        req.set_query_string(&req_url.query_string);
         let content_encoding = req.content_encoding.clone();
-        let mut res = Response::new(stream.try_clone().unwrap(), content_encoding.clone());
+        let mut res = Response::new(
+            stream.try_clone().map_err(|e| HttpServerError::new(&e.to_string()))?,
+            content_encoding.clone()
+        );
+        res.set_version(req_url.version);
+        #[cfg(feature = "secure-cookies")]
+        {
+            req.cookie_key = context.cookie_key.clone();
+            res.cookie_key = context.cookie_key.clone();
+        }
+        if let Some((store, config)) = &context.sessions {
+            let id = req.get_cookie(&config.cookie.name).map(|cookie| cookie.value.as_str());
+            let session = Session::load(store.clone(), config.clone(), id);
+            res.set_session(session.clone());
+            req.session = Some(session);
+        }
         // Check if supported req type
         let content_type = req.content_type.clone().unwrap_or(ContentType::None);
 
@@ -317,18 +616,57 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
             return Err(HttpServerError::new("Bad request!"));
         }
         //Check if over content length
-        if max_content_length > 0 && req.content_length > max_content_length && has_body {
+        if
+            context.max_content_length > 0 &&
+            req.content_length > context.max_content_length &&
+            has_body
+        {
             req.read_only_body(&mut bfreader);
             res.send_code(ResponseCode::ContentTooLarge)?;
             return Err(HttpServerError::new("Content too large!"));
         }
+        // CORS (before the middleware, preflights don't have credentials)
+        if let Some(cors) = &context.cors {
+            if cors.handle(&req_url.req_type, &req, &mut res)? {
+                return Ok(());
+            }
+        }
+        // Rate limit
+        if let Some(status) = context.rate_limiter.check(&req_url.path, &req) {
+            status.set_headers(&mut res);
+            if !status.allowed {
+                if has_body {
+                    req.read_only_body(&mut bfreader);
+                }
+                res.send_code(ResponseCode::TooManyRequests)?;
+                return Err(HttpServerError::new("Too many requests!"));
+            }
+        }
+        // Authentication
+        if let Some((_, auth)) = context.auth.iter().find(|(prefix, _)| matches_prefix(prefix, &req_url.path)) {
+            match auth.authenticate(&req) {
+                Ok(principal) => {
+                    req.principal = Some(principal);
+                }
+                Err(err) => {
+                    if has_body {
+                        req.read_only_body(&mut bfreader);
+                    }
+                    res.set_header(&Header::new("WWW-Authenticate", &auth.challenge(&req)));
+                    res.send_code(ResponseCode::Unauthorized)?;
+                    return Err(err);
+                }
+            }
+        }
         // Middleware
-        if middleware.is_some() {
-            let result = middleware.unwrap()(
+        if let Some(middleware) = context.middleware {
+            let result = middleware(
                 &(Url {
                     path: req_url.path.clone(),
                     req_type: req_url.req_type.clone(),
                     query: HashMap::new(),
+                    query_string: String::new(),
+                    version: req_url.version,
                 }),
                 &req,
                 &mut res,
@@ -342,10 +680,10 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
         let mut matching_routes: Vec<EndPoint<T>> = Vec::new();
         let mut params: HashMap<String, String> = HashMap::new();
         // Check for matching pattern
-        for route in routes {
+        for route in &context.routes {
             let match_pattern = Url::match_patern(&req_url.path.clone(), &route.path.clone());
             if match_pattern.0 == true {
-                matching_routes.push(route);
+                matching_routes.push(route.clone());
                 if params.is_empty() {
                     params = match_pattern.1;
                 }
@@ -371,67 +709,80 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
             req.params = params;
 
             if has_body {
-                req.extract_body(&mut bfreader, bump)?;
+                if let Err(err) = req.extract_body(&mut bfreader, bump) {
+                    // Sent on the response so it keeps the CORS headers
+                    if let Some(code) = err.code {
+                        res.send_code(code)?;
+                    }
+                    return Err(err);
+                }
             }
 
-            (route.handle)(req, res, public_var)?;
+            let sent = res.sent_flag();
+            // Headers set until now (like CORS) are sent with the error too
+            let headers = res.headers().clone();
+            if let Err(err) = (route.handle)(req, res, public_var) {
+                // Errors with a code are sent if the handler didn't send anything
+                if !sent.load(std::sync::atomic::Ordering::Relaxed) {
+                    return Self::reject_with_reason(&stream, err, &headers);
+                }
+                return Err(err);
+            }
 
             return Ok(());
         }
 
-        let mut sent = false;
-        for route in static_routes {
-            if req_url.path.starts_with(&route.0.0) {
-                let parts: Vec<&str> = req_url.path.split(&route.0.0).collect();
-                if parts.len() == 0 {
-                    continue;
-                }
-                let path_str = route.1 + parts[1];
-                let path = Path::new(&path_str);
-
-                if path.exists() && path.is_file() {
-                    match File::open(path) {
-                        Ok(file) => {
-                            let metadata = file.metadata();
-
-                            let mut bfreader = BufReader::new(file);
-
-                            let mut size: Option<u64> = None;
-                            if metadata.is_ok() {
-                                size = Some(metadata.unwrap().len());
-                            }
-                            let extension = path.extension().unwrap_or_default();
-
-                            let content_type = ContentType::from_extension(
-                                extension.to_str().unwrap_or_default()
-                            );
-                            if route.0.1 {
-                                res.pipe_stream(bfreader, content_type, size.as_ref())?;
-                            } else {
-                                let mut buff: Vec<u8> = Vec::new();
-                                bfreader
-                                    .read_to_end(&mut buff)
-                                    .map_err(|e| HttpServerError::new(&e.to_string()))?;
-                                res.send_bytes(&buff, content_type)?;
-                            }
-                        }
-                        Err(_err) => {
-                            res.send_code(ResponseCode::NotFound)?;
-                        }
-                    }
-                } else {
-                    res.send_code(ResponseCode::NotFound)?;
-                }
-
-                sent = true;
-                break;
+        for route in &context.static_routes {
+            if let Some(rest) = route.strip_mount(&req_url.path) {
+                return static_files::serve_static(
+                    route,
+                    rest,
+                    &context.static_options,
+                    &req,
+                    &mut res
+                );
+            }
+        }
+        res.send_code(ResponseCode::NotFound)?;
+        return Err(HttpServerError::new("Not found!"));
+    }
+    /// Parse errors are bad requests unless they say otherwise
+    fn bad_request(mut err: HttpServerError) -> HttpServerError {
+        if err.code.is_none() {
+            err.code = Some(ResponseCode::BadRequest);
+        }
+        err
+    }
+    /// Sends the response code of a request that can't be handled and returns the error.
+    /// Errors without a code (like a closed connection) get no response.
+    fn reject(stream: &TcpStream, err: HttpServerError) -> Result<(), HttpServerError> {
+        if let Some(code) = err.code {
+            if let Ok(stream) = stream.try_clone() {
+                let mut res = Response::new(stream, None);
+                res.set_header(&Header::new("Connection", "close"));
+                res.send_code(code)?;
             }
         }
-        if sent == false {
-            res.send_code(ResponseCode::NotFound)?;
-            return Err(HttpServerError::new("Not found!"));
+        Err(err)
+    }
+    /// Like `reject` but the reason is sent as the body, with the given headers
+    fn reject_with_reason(
+        stream: &TcpStream,
+        err: HttpServerError,
+        headers: &HeaderMap
+    ) -> Result<(), HttpServerError> {
+        if let Some(code) = err.code {
+            if let Ok(stream) = stream.try_clone() {
+                let mut res = Response::new(stream, None);
+                for header in headers.iter() {
+                    res.append_header(header);
+                }
+                res.set_status(&code);
+                res.set_header(&Header::new("Connection", "close"));
+                res.send_string(&err.reason)?;
+            }
         }
-        return Ok(());
+        Err(err)
     }
     ///Locks the thread from stoping (put it in the end of the main file to keep the server running);
     pub fn lock() {
@@ -442,6 +793,32 @@ impl<T: Clone + std::marker::Send + 'static> Server<T> {
     }
 }
 
+/// Everything a worker needs to handle a request, shared between the threads.
+struct Context<T: Clone + std::marker::Send + 'static> {
+    max_content_length: usize,
+    max_decompressed_length: usize,
+    header_limits: HeaderLimits,
+    idle_timeout: Option<Duration>,
+    header_timeout: Option<Duration>,
+    body_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    multipart_limits: MultipartLimits,
+    routes: Vec<EndPoint<T>>,
+    static_routes: Vec<StaticEndpoint>,
+    static_options: StaticOptions,
+    middleware: Option<
+        fn(url: &Url, req: &Request, res: &mut Response, public_var: &Option<T>) -> bool
+    >,
+    #[cfg(feature = "secure-cookies")]
+    cookie_key: Option<Arc<CookieKey>>,
+    sessions: Option<(Arc<dyn SessionStore>, Arc<SessionConfig>)>,
+    cors: Option<Arc<Cors>>,
+    rate_limiter: RateLimiter,
+    trusted_proxies: Vec<IpNet>,
+    proxy_protocol: bool,
+    auth: Vec<(String, Auth)>,
+}
+
 fn order_routes<T: Clone + Send + 'static>(routes: &mut Vec<EndPoint<T>>) {
     routes.sort_by(|a, b| {
         let path_a = &a.path;
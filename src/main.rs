@@ -4,6 +4,7 @@ use std::io::{ BufReader, Read, Seek, SeekFrom, Write };
 use choki::src::request::Request;
 use choki::src::response::Response;
 use choki::src::structs::{ ContentType, Header, HttpServerError, RequestType, ResponseCode, Url };
+use choki::src::cache::StaticCache;
 use choki::Server;
 
 fn main() {
@@ -62,7 +63,16 @@ fn main() {
             Ok(())
         })
         .unwrap();
+    server.use_static_cache(StaticCache::new(64, 64 * 1024, None));
     server.new_static("/static", "./tests/static", false).unwrap();
+    server
+        .new_embedded(
+            "/embedded",
+            choki::embed_assets! {
+                "index.html" => "../tests/static/index.html",
+            }
+        )
+        .unwrap();
     server.listen(3000, None, None, || { println!("Server is listening on port 3000") }).unwrap();
     Server::<i32>::lock();
 }
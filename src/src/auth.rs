@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+#[cfg(feature = "jwt")]
+use std::sync::Arc;
+
+#[cfg(feature = "jwt")]
+use super::jwt::JwtValidator;
+use super::{ request::Request, structs::* };
+
+/// The authenticated user or client of a request (`req.principal`).
+#[derive(Clone, Debug, Default)]
+pub struct Principal {
+    pub name: String,
+    /// Anything the check wants to pass to the handlers (roles, ids...)
+    pub data: HashMap<String, String>,
+    /// The claims of the token with `Auth::Jwt`
+    #[cfg(feature = "jwt")]
+    pub claims: Option<serde_json::Value>,
+}
+
+impl Principal {
+    pub fn new(name: &str) -> Principal {
+        return Principal {
+            name: name.to_owned(),
+            data: HashMap::new(),
+            #[cfg(feature = "jwt")]
+            claims: None,
+        };
+    }
+    pub fn with_data(mut self, key: &str, value: &str) -> Principal {
+        self.data.insert(key.to_owned(), value.to_owned());
+        self
+    }
+    /// The JWT claims as a struct (401 if they don't match)
+    #[cfg(feature = "jwt")]
+    pub fn claims_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpServerError> {
+        let claims = self.claims.clone().ok_or_else(|| unauthorized("No token claims!"))?;
+        serde_json::from_value(claims).map_err(|e| unauthorized(&format!("Invalid claims: {}", e)))
+    }
+}
+
+/// Authentication for a group of routes (`server.use_auth`). Requests without valid
+/// credentials get 401 with a WWW-Authenticate challenge.
+#[derive(Clone)]
+pub enum Auth {
+    /// HTTP Basic, the check gets the user and password
+    Basic {
+        realm: String,
+        check: fn(user: &str, password: &str) -> Option<Principal>,
+    },
+    /// Bearer token (RFC 6750), the check gets the token
+    Bearer {
+        realm: String,
+        validate: fn(token: &str) -> Option<Principal>,
+    },
+    /// Bearer JSON Web Token, the principal name is the `sub` claim
+    #[cfg(feature = "jwt")]
+    Jwt {
+        realm: String,
+        validator: Arc<JwtValidator>,
+    },
+}
+
+impl Auth {
+    pub fn basic(realm: &str, check: fn(user: &str, password: &str) -> Option<Principal>) -> Auth {
+        Auth::Basic { realm: realm.to_owned(), check: check }
+    }
+    pub fn bearer(realm: &str, validate: fn(token: &str) -> Option<Principal>) -> Auth {
+        Auth::Bearer { realm: realm.to_owned(), validate: validate }
+    }
+    #[cfg(feature = "jwt")]
+    pub fn jwt(realm: &str, validator: JwtValidator) -> Auth {
+        Auth::Jwt { realm: realm.to_owned(), validator: Arc::new(validator) }
+    }
+    /// The principal of the request, 401 if the credentials are missing or wrong
+    pub fn authenticate(&self, req: &Request) -> Result<Principal, HttpServerError> {
+        let principal = match self {
+            Auth::Basic { check, .. } => {
+                let (user, password) = req.basic_auth().ok_or_else(|| unauthorized("Missing credentials!"))?;
+                check(&user, &password)
+            }
+            Auth::Bearer { validate, .. } => {
+                let token = req.bearer_token().ok_or_else(|| unauthorized("Missing token!"))?;
+                validate(token)
+            }
+            #[cfg(feature = "jwt")]
+            Auth::Jwt { validator, .. } => {
+                let token = req.bearer_token().ok_or_else(|| unauthorized("Missing token!"))?;
+                let claims = validator.validate(token)?;
+                let mut principal = Principal::new(
+                    claims
+                        .get("sub")
+                        .and_then(|sub| sub.as_str())
+                        .unwrap_or_default()
+                );
+                principal.claims = Some(claims);
+                Some(principal)
+            }
+        };
+        principal.ok_or_else(|| unauthorized("Invalid credentials!"))
+    }
+    /// The WWW-Authenticate header for a request that failed
+    pub fn challenge(&self, req: &Request) -> String {
+        match self {
+            Auth::Basic { realm, .. } => format!("Basic realm=\"{}\", charset=\"UTF-8\"", quote(realm)),
+            Auth::Bearer { realm, .. } => bearer_challenge(realm, req),
+            #[cfg(feature = "jwt")]
+            Auth::Jwt { realm, .. } => bearer_challenge(realm, req),
+        }
+    }
+}
+
+/// Compares secrets in a time that doesn't depend on where they differ
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn bearer_challenge(realm: &str, req: &Request) -> String {
+    // No error code if no token was sent (RFC 6750 3.1)
+    if req.bearer_token().is_some() {
+        return format!("Bearer realm=\"{}\", error=\"invalid_token\"", quote(realm));
+    }
+    format!("Bearer realm=\"{}\"", quote(realm))
+}
+
+fn unauthorized(reason: &str) -> HttpServerError {
+    HttpServerError::with_code(ResponseCode::Unauthorized, reason)
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+    sync::{ atomic::{ AtomicU64, Ordering }, Arc, Mutex, OnceLock },
+    time::{ Duration, Instant, SystemTime },
+};
+
+use flate2::{ write::GzEncoder, Compression };
+
+use super::structs::ContentType;
+
+/// A static file held in memory.
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub content_type: Option<ContentType>,
+    pub etag: String,
+    modified: Option<SystemTime>,
+    gzip: OnceLock<Vec<u8>>,
+    checked: Mutex<Instant>,
+    last_used: AtomicU64,
+}
+
+impl CachedFile {
+    pub fn new(
+        path: PathBuf,
+        data: Vec<u8>,
+        content_type: Option<ContentType>,
+        etag: String,
+        modified: Option<SystemTime>
+    ) -> CachedFile {
+        return CachedFile {
+            path: path,
+            data: data,
+            content_type: content_type,
+            etag: etag,
+            modified: modified,
+            gzip: OnceLock::new(),
+            checked: Mutex::new(Instant::now()),
+            last_used: AtomicU64::new(0),
+        };
+    }
+    /// The gzip compressed data, compressed on first use.
+    pub fn gzip(&self) -> &[u8] {
+        self.gzip.get_or_init(|| gzip(&self.data))
+    }
+}
+
+/// Bounded LRU cache of small static files, keyed by their url path (for example "/static/index.html").
+///
+/// Entries are checked against the file modification time and size before being served.
+/// With `revalidate` set, that check is done at most once per interval so hot files are served
+/// without any syscalls. `invalidate` and `clear` can be called from your own file watcher.
+pub struct StaticCache {
+    pub max_entries: usize,
+    pub max_file_size: u64,
+    pub revalidate: Option<Duration>,
+
+    entries: Mutex<HashMap<String, Arc<CachedFile>>>,
+    tick: AtomicU64,
+}
+
+impl StaticCache {
+    pub fn new(max_entries: usize, max_file_size: u64, revalidate: Option<Duration>) -> StaticCache {
+        return StaticCache {
+            max_entries: max_entries,
+            max_file_size: max_file_size,
+            revalidate: revalidate,
+            entries: Mutex::new(HashMap::new()),
+            tick: AtomicU64::new(0),
+        };
+    }
+    /// Gets a fresh entry. Stale entries (changed or deleted file) are removed.
+    pub fn get(&self, key: &str) -> Option<Arc<CachedFile>> {
+        let entry = self.entries.lock().ok()?.get(key)?.clone();
+
+        if !self.is_fresh(&entry) {
+            self.invalidate(key);
+            return None;
+        }
+        entry.last_used.store(self.tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        Some(entry)
+    }
+    /// True if a file of this size can be cached
+    pub fn accepts(&self, size: u64) -> bool {
+        self.max_entries > 0 && size <= self.max_file_size
+    }
+    /// Adds an entry, evicting the least recently used one if full.
+    /// Files that are not accepted are returned without being cached.
+    pub fn insert(&self, key: &str, file: CachedFile) -> Arc<CachedFile> {
+        file.last_used.store(self.tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        let file = Arc::new(file);
+        if !self.accepts(file.data.len() as u64) {
+            return file;
+        }
+
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => {
+                return file;
+            }
+        };
+        if !entries.contains_key(key) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_owned(), file.clone());
+        file
+    }
+    /// Removes a single entry
+    pub fn invalidate(&self, key: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(key);
+        }
+    }
+    /// Removes every entry
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .map(|entries| entries.len())
+            .unwrap_or_default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_fresh(&self, entry: &CachedFile) -> bool {
+        if let Some(interval) = self.revalidate {
+            match entry.checked.lock() {
+                Ok(mut checked) => {
+                    if checked.elapsed() < interval {
+                        return true;
+                    }
+                    *checked = Instant::now();
+                }
+                Err(_) => {
+                    return false;
+                }
+            }
+        }
+        match fs::metadata(&entry.path) {
+            Ok(metadata) => {
+                metadata.len() == (entry.data.len() as u64) &&
+                    metadata.modified().ok() == entry.modified
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Gzip compresses data with the default level
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap_or_default();
+    encoder.finish().unwrap_or_default()
+}
@@ -0,0 +1,114 @@
+use std::{
+    io::Read,
+    net::{ Shutdown, TcpStream },
+    sync::atomic::{ AtomicU64, Ordering },
+    time::Duration,
+};
+
+use super::{ response::Response, structs::* };
+
+/// Connection counters, for monitoring. Get them with `server.stats()`.
+#[derive(Default)]
+pub struct ServerStats {
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    queued: AtomicU64,
+    active: AtomicU64,
+    completed: AtomicU64,
+}
+
+impl ServerStats {
+    /// Connections accepted since the start (rejected ones included)
+    pub fn accepted(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+    /// Connections turned away because the server was full
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+    /// Connections waiting for a free thread
+    pub fn queued(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+    /// Connections being handled
+    pub fn active(&self) -> u64 {
+        self.active.load(Ordering::Relaxed)
+    }
+    /// Connections handled since the start
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Relaxed)
+    }
+
+    /// Counts an accepted connection, false if it must be rejected
+    pub(crate) fn try_queue(&self, limits: &ConnectionLimits) -> bool {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+        let queued = self.queued();
+        let open = queued + self.active();
+        let full =
+            (limits.max_queue > 0 && queued >= (limits.max_queue as u64)) ||
+            (limits.max_connections > 0 && open >= (limits.max_connections as u64));
+        if full {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+    /// Moves a queued connection to active until the guard is dropped
+    pub(crate) fn start(&self) -> ActiveConnection<'_> {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.active.fetch_add(1, Ordering::Relaxed);
+        ActiveConnection { stats: self }
+    }
+}
+
+/// Counts the connection as done even if the handler panics
+pub(crate) struct ActiveConnection<'a> {
+    stats: &'a ServerStats,
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.stats.active.fetch_sub(1, Ordering::Relaxed);
+        self.stats.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionLimits {
+    /// Max connections handled or waiting at once (0 for no limit)
+    pub max_connections: usize,
+    /// Max connections waiting for a free thread (0 for no limit)
+    pub max_queue: usize,
+    /// Connections over the limits get 503 with this Retry-After, or are closed if None
+    pub retry_after: Option<Duration>,
+}
+
+/// Answers a connection the server has no room for. Runs on the accept thread so it
+/// never waits on the client.
+pub(crate) fn reject_connection(stream: TcpStream, limits: &ConnectionLimits) {
+    if let Some(retry_after) = limits.retry_after {
+        let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+        if let Ok(write_stream) = stream.try_clone() {
+            let mut res = Response::new(write_stream, None);
+            res.set_header(&Header::new("Retry-After", &retry_after.as_secs().max(1).to_string()));
+            res.set_header(&Header::new("Connection", "close"));
+            let _ = res.send_code(ResponseCode::ServiceUnavailable);
+        }
+    }
+    // Closing with unread data resets the connection and the client could lose the response,
+    // so what already arrived is read first
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream.set_nonblocking(true).is_ok() {
+        let mut buffer = [0u8; 4096];
+        let mut stream = &stream;
+        for _ in 0..16 {
+            match stream.read(&mut buffer) {
+                Ok(size) if size > 0 => {}
+                _ => {
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use base64::{ engine::general_purpose::URL_SAFE_NO_PAD, Engine };
+use chacha20poly1305::{ aead::{ Aead, KeyInit, Payload }, ChaCha20Poly1305, Key, Nonce };
+use hmac::{ Hmac, Mac };
+use sha2::Sha256;
+
+use super::structs::{ Cookie, HttpServerError, ResponseCode };
+
+type HmacSha256 = Hmac<Sha256>;
+
+const NONCE_SIZE: usize = 12;
+
+/// Keys for signed and private (encrypted) cookies, derived from a server secret.
+///
+/// Signed cookies can be read but not changed by the client, private cookies can't be read either.
+/// The cookie name is part of the signature so a value can't be moved to another cookie.
+pub struct CookieKey {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl CookieKey {
+    /// The secret must be at least 32 bytes of random data. Keep it the same across restarts
+    /// or the cookies sent before become invalid.
+    pub fn new(secret: &[u8]) -> Result<CookieKey, HttpServerError> {
+        if secret.len() < 32 {
+            return Err(HttpServerError::new("Cookie secret must be at least 32 bytes!"));
+        }
+        return Ok(CookieKey {
+            signing: derive(secret, b"choki cookie signing"),
+            encryption: derive(secret, b"choki cookie encryption"),
+        });
+    }
+    /// Random key, cookies from before a restart become invalid
+    pub fn generate() -> Result<CookieKey, HttpServerError> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(|e|
+            HttpServerError::new(&format!("Failed to generate cookie key: {}", e))
+        )?;
+        CookieKey::new(&secret)
+    }
+    /// Copy of the cookie with the signature added to the value
+    pub fn sign(&self, cookie: &Cookie) -> Cookie {
+        let mut signed = cookie.clone();
+        let mac = self.mac(&cookie.name, &cookie.value).finalize().into_bytes();
+        signed.value = format!("{}.{}", cookie.value, URL_SAFE_NO_PAD.encode(mac));
+        signed
+    }
+    /// Copy of the cookie with the original value if the signature is valid
+    pub fn verify(&self, cookie: &Cookie) -> Option<Cookie> {
+        let (value, mac) = cookie.value.rsplit_once('.')?;
+        let mac = URL_SAFE_NO_PAD.decode(mac).ok()?;
+        self.mac(&cookie.name, value).verify_slice(&mac).ok()?;
+
+        let mut verified = cookie.clone();
+        verified.value = value.to_owned();
+        Some(verified)
+    }
+    /// Copy of the cookie with the value encrypted (ChaCha20-Poly1305)
+    pub fn encrypt(&self, cookie: &Cookie) -> Result<Cookie, HttpServerError> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        getrandom::getrandom(&mut nonce).map_err(|e|
+            HttpServerError::with_code(
+                ResponseCode::InternalServerError,
+                &format!("Failed to generate nonce: {}", e)
+            )
+        )?;
+        let payload = Payload { msg: cookie.value.as_bytes(), aad: cookie.name.as_bytes() };
+        let sealed = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_|
+                HttpServerError::with_code(
+                    ResponseCode::InternalServerError,
+                    "Failed to encrypt cookie!"
+                )
+            )?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&sealed);
+        let mut encrypted = cookie.clone();
+        encrypted.value = URL_SAFE_NO_PAD.encode(data);
+        Ok(encrypted)
+    }
+    /// Copy of the cookie with the value decrypted if it wasn't changed
+    pub fn decrypt(&self, cookie: &Cookie) -> Option<Cookie> {
+        let data = URL_SAFE_NO_PAD.decode(&cookie.value).ok()?;
+        if data.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, sealed) = data.split_at(NONCE_SIZE);
+        let payload = Payload { msg: sealed, aad: cookie.name.as_bytes() };
+        let value = self.cipher().decrypt(Nonce::from_slice(nonce), payload).ok()?;
+
+        let mut decrypted = cookie.clone();
+        decrypted.value = String::from_utf8(value).ok()?;
+        Some(decrypted)
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing).expect(
+            "HMAC accepts any key size"
+        );
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.encryption))
+    }
+}
+
+/// Separate keys for every use from the same secret
+fn derive(secret: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts any key size");
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CookieKey;
+    use crate::src::structs::Cookie;
+
+    fn key() -> CookieKey {
+        CookieKey::new(&[7u8; 32]).unwrap()
+    }
+
+    #[test]
+    fn signed_cookie_roundtrip() {
+        let signed = key().sign(&Cookie::new("user", "alice"));
+        assert_eq!(key().verify(&signed).unwrap().value, "alice");
+    }
+
+    #[test]
+    fn rejects_tampered_signed_cookies() {
+        let signed = key().sign(&Cookie::new("user", "alice"));
+        let (_, mac) = signed.value.rsplit_once('.').unwrap();
+
+        let changed_value = Cookie::new("user", &format!("admin.{}", mac));
+        assert!(key().verify(&changed_value).is_none());
+        let moved = Cookie::new("role", &signed.value);
+        assert!(key().verify(&moved).is_none());
+        assert!(key().verify(&Cookie::new("user", "alice")).is_none());
+        let other_key = CookieKey::new(&[8u8; 32]).unwrap();
+        assert!(other_key.verify(&signed).is_none());
+    }
+
+    #[test]
+    fn rejects_tampered_private_cookies() {
+        let encrypted = key().encrypt(&Cookie::new("user", "alice")).unwrap();
+        assert_ne!(encrypted.value, "alice");
+        assert_eq!(key().decrypt(&encrypted).unwrap().value, "alice");
+
+        let mut changed = encrypted.clone();
+        let last = if changed.value.ends_with('A') { "B" } else { "A" };
+        changed.value.replace_range(changed.value.len() - 1.., last);
+        assert!(key().decrypt(&changed).is_none());
+        let moved = Cookie::new("role", &encrypted.value);
+        assert!(key().decrypt(&moved).is_none());
+        assert!(key().decrypt(&Cookie::new("user", "AAAA")).is_none());
+        let other_key = CookieKey::new(&[8u8; 32]).unwrap();
+        assert!(other_key.decrypt(&encrypted).is_none());
+    }
+}
@@ -0,0 +1,283 @@
+use std::time::Duration;
+
+use super::{ request::Request, response::Response, structs::* };
+
+/// Which origins can call the server from a browser.
+#[derive(Clone)]
+pub enum AllowedOrigin {
+    /// Every origin ("*", or the origin itself with credentials)
+    Any,
+    /// An exact origin ("https://example.com") or one with a wildcard ("https://*.example.com")
+    Pattern(String),
+    /// Decides from the origin
+    Predicate(fn(origin: &str) -> bool),
+}
+
+impl AllowedOrigin {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigin::Any => true,
+            AllowedOrigin::Pattern(pattern) => {
+                match pattern.split_once('*') {
+                    Some((prefix, suffix)) => {
+                        let origin = origin.to_ascii_lowercase();
+                        origin.len() > prefix.len() + suffix.len() &&
+                            origin.starts_with(&prefix.to_ascii_lowercase()) &&
+                            origin.ends_with(&suffix.to_ascii_lowercase()) &&
+                            // The wildcard can only be part of the host
+                            !origin[prefix.len()..origin.len() - suffix.len()].contains(['/', ':', '@'])
+                    }
+                    None => pattern.eq_ignore_ascii_case(origin),
+                }
+            }
+            AllowedOrigin::Predicate(predicate) => predicate(origin),
+        }
+    }
+}
+
+/// Cross-origin resource sharing (CORS) settings. Preflight requests (OPTIONS with
+/// Access-Control-Request-Method) are answered automatically and the other requests
+/// from allowed origins get the Access-Control-* headers.
+#[derive(Clone)]
+pub struct Cors {
+    pub origins: Vec<AllowedOrigin>,
+    pub methods: Vec<RequestType>,
+    /// Request headers the client can send, None for any
+    pub headers: Option<Vec<String>>,
+    /// Response headers the client can read
+    pub exposed_headers: Vec<String>,
+    /// Allow cookies and Authorization
+    pub credentials: bool,
+    /// How long the browser can cache a preflight
+    pub max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        return Cors {
+            origins: Vec::new(),
+            methods: vec![
+                RequestType::Get,
+                RequestType::Head,
+                RequestType::Post,
+                RequestType::Put,
+                RequestType::Patch,
+                RequestType::Delete
+            ],
+            headers: None,
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        };
+    }
+}
+
+impl Cors {
+    /// No origins allowed until added
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+    pub fn with_any_origin(mut self) -> Cors {
+        self.origins.push(AllowedOrigin::Any);
+        self
+    }
+    /// Exact origin or with a wildcard in the host ("https://*.example.com")
+    pub fn with_origin(mut self, origin: &str) -> Cors {
+        self.origins.push(AllowedOrigin::Pattern(origin.trim_end_matches('/').to_owned()));
+        self
+    }
+    pub fn with_origin_fn(mut self, predicate: fn(origin: &str) -> bool) -> Cors {
+        self.origins.push(AllowedOrigin::Predicate(predicate));
+        self
+    }
+    pub fn with_methods(mut self, methods: &[RequestType]) -> Cors {
+        self.methods = methods.to_vec();
+        self
+    }
+    pub fn with_headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = Some(
+            headers
+                .iter()
+                .map(|header| header.to_ascii_lowercase())
+                .collect()
+        );
+        self
+    }
+    pub fn with_exposed_headers(mut self, headers: &[&str]) -> Cors {
+        self.exposed_headers = headers
+            .iter()
+            .map(|header| header.to_string())
+            .collect();
+        self
+    }
+    pub fn with_credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+    pub fn with_max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    /// Adds the CORS headers to the response. Returns true if the request was a preflight
+    /// and got answered (204, or 403 if not allowed).
+    pub fn handle(
+        &self,
+        req_type: &RequestType,
+        req: &Request,
+        res: &mut Response
+    ) -> Result<bool, HttpServerError> {
+        let origin = match req.headers.get("Origin") {
+            Some(origin) => origin.to_owned(),
+            None => {
+                return Ok(false);
+            }
+        };
+        let is_any = !self.credentials && self.origins.iter().any(|allowed| matches!(allowed, AllowedOrigin::Any));
+        if !is_any {
+            // The answer depends on the origin so caches must keep them apart
+            res.append_header(&Header::new("Vary", "Origin"));
+        }
+
+        let request_method = req.headers.get("Access-Control-Request-Method");
+        let is_preflight = *req_type == RequestType::Options && request_method.is_some();
+
+        if !self.is_origin_allowed(&origin) {
+            if is_preflight {
+                res.send_code(ResponseCode::Forbidden)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        res.set_header(&Header::new("Access-Control-Allow-Origin", if is_any { "*" } else { &origin }));
+        if self.credentials {
+            res.set_header(&Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+
+        if !is_preflight {
+            if !self.exposed_headers.is_empty() {
+                res.set_header(
+                    &Header::new("Access-Control-Expose-Headers", &self.exposed_headers.join(", "))
+                );
+            }
+            return Ok(false);
+        }
+
+        // A method that can't be parsed isn't allowed
+        let request_method = RequestType::from_string(request_method.unwrap_or_default().trim()).ok();
+        let request_headers: Vec<String> = req.headers
+            .get_all("Access-Control-Request-Headers")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|header| header.trim().to_ascii_lowercase())
+            .filter(|header| !header.is_empty())
+            .collect();
+
+        let method_allowed = request_method.map_or(false, |method| self.methods.contains(&method));
+        let headers_allowed = match &self.headers {
+            Some(headers) => request_headers.iter().all(|header| headers.contains(header)),
+            None => true,
+        };
+        if !method_allowed || !headers_allowed {
+            res.delete_header("Access-Control-Allow-Origin");
+            res.delete_header("Access-Control-Allow-Credentials");
+            res.send_code(ResponseCode::Forbidden)?;
+            return Ok(true);
+        }
+
+        let methods: Vec<&str> = self.methods
+            .iter()
+            .map(|method| method.as_str())
+            .collect();
+        res.set_header(&Header::new("Access-Control-Allow-Methods", &methods.join(", ")));
+        let allowed_headers = match &self.headers {
+            Some(headers) => headers.join(", "),
+            // Any header, the requested ones are sent back ("*" doesn't work with credentials)
+            None => request_headers.join(", "),
+        };
+        if !allowed_headers.is_empty() {
+            res.set_header(&Header::new("Access-Control-Allow-Headers", &allowed_headers));
+        }
+        if let Some(max_age) = self.max_age {
+            res.set_header(&Header::new("Access-Control-Max-Age", &max_age.as_secs().to_string()));
+        }
+        res.send_code(ResponseCode::NoContent)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ io::Read, net::{ TcpListener, TcpStream } };
+
+    /// Runs the request through the CORS check, returns if it was answered and what was sent
+    fn handle(cors: &Cors, req_type: RequestType, headers: &[&str]) -> (bool, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let req = Request::parse(&headers.to_vec(), None, None).unwrap();
+        let mut res = Response::new(server, None);
+        let answered = cors.handle(&req_type, &req, &mut res).unwrap();
+        drop(res);
+
+        let mut sent = String::new();
+        client.read_to_string(&mut sent).unwrap();
+        (answered, sent)
+    }
+
+    fn cors() -> Cors {
+        Cors::new().with_origin("https://example.com").with_methods(&[RequestType::Get, RequestType::Post])
+    }
+
+    #[test]
+    fn allowed_preflight() {
+        let (answered, sent) = handle(&cors(), RequestType::Options, &[
+            "Origin: https://example.com",
+            "Access-Control-Request-Method: POST",
+        ]);
+        assert!(answered);
+        assert!(sent.starts_with("HTTP/1.1 204"));
+        assert!(sent.contains("Access-Control-Allow-Origin: https://example.com\r\n"));
+        assert!(sent.contains("Access-Control-Allow-Methods: GET, POST\r\n"));
+    }
+
+    #[test]
+    fn preflight_with_disallowed_origin() {
+        let (answered, sent) = handle(&cors(), RequestType::Options, &[
+            "Origin: https://evil.com",
+            "Access-Control-Request-Method: POST",
+        ]);
+        assert!(answered);
+        assert!(sent.starts_with("HTTP/1.1 403"));
+        assert!(!sent.contains("Access-Control-Allow-"));
+    }
+
+    #[test]
+    fn preflight_with_disallowed_method() {
+        for method in ["DELETE", "NOT A METHOD"] {
+            let header = format!("Access-Control-Request-Method: {}", method);
+            let (answered, sent) = handle(&cors(), RequestType::Options, &[
+                "Origin: https://example.com",
+                &header,
+            ]);
+            assert!(answered);
+            assert!(sent.starts_with("HTTP/1.1 403"));
+            assert!(!sent.contains("Access-Control-Allow-"));
+        }
+    }
+
+    #[test]
+    fn wildcard_origin_only_matches_the_host() {
+        let cors = Cors::new().with_origin("https://*.example.com");
+        assert!(cors.is_origin_allowed("https://api.example.com"));
+        assert!(!cors.is_origin_allowed("https://example.com"));
+        assert!(!cors.is_origin_allowed("https://evil.com/.example.com"));
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    fs,
+    path::{ Path, PathBuf },
+    sync::{ Mutex, RwLock },
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
+};
+
+use base64::{ engine::general_purpose::URL_SAFE_NO_PAD, Engine };
+use hmac::{ Hmac, Mac };
+use rsa::{ pkcs8::DecodePublicKey, BigUint, Pkcs1v15Sign, RsaPublicKey };
+use serde_json::Value;
+use sha2::{ Digest, Sha256 };
+
+use super::structs::{ HttpServerError, ResponseCode };
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How often the JWKS file is checked for changes
+const JWKS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+enum KeyMaterial {
+    /// HS256
+    Hmac(Vec<u8>),
+    /// RS256
+    Rsa(RsaPublicKey),
+}
+
+struct JwtKey {
+    kid: Option<String>,
+    material: KeyMaterial,
+}
+
+struct JwksFile {
+    path: PathBuf,
+    keys: RwLock<Vec<JwtKey>>,
+    /// (modified time of the loaded file, last check)
+    state: Mutex<(Option<SystemTime>, Instant)>,
+}
+
+/// Verifies HS256 and RS256 JSON Web Tokens and their `exp`, `nbf`, `iss` and `aud` claims.
+/// Use it with `Auth::jwt` to protect routes, the claims are in `req.principal`.
+pub struct JwtValidator {
+    keys: Vec<JwtKey>,
+    jwks: Option<JwksFile>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required value in the `aud` claim
+    pub audience: Option<String>,
+    /// Clock difference allowed for `exp` and `nbf`
+    pub leeway: Duration,
+    /// If true, tokens without `exp` are rejected
+    pub require_exp: bool,
+}
+
+impl JwtValidator {
+    /// No keys until added
+    pub fn new() -> JwtValidator {
+        return JwtValidator {
+            keys: Vec::new(),
+            jwks: None,
+            issuer: None,
+            audience: None,
+            leeway: Duration::from_secs(60),
+            require_exp: true,
+        };
+    }
+    /// Secret for HS256 tokens, an empty secret is an error
+    pub fn with_hmac_secret(mut self, secret: &[u8]) -> Result<JwtValidator, HttpServerError> {
+        if secret.is_empty() {
+            return Err(HttpServerError::new("Empty HMAC secret!"));
+        }
+        self.keys.push(JwtKey { kid: None, material: KeyMaterial::Hmac(secret.to_vec()) });
+        Ok(self)
+    }
+    /// Public key for RS256 tokens ("-----BEGIN PUBLIC KEY-----")
+    pub fn with_rsa_pem(mut self, pem: &str) -> Result<JwtValidator, HttpServerError> {
+        let key = RsaPublicKey::from_public_key_pem(pem).map_err(|e|
+            HttpServerError::new(&format!("Invalid RSA public key: {}", e))
+        )?;
+        self.keys.push(JwtKey { kid: None, material: KeyMaterial::Rsa(key) });
+        Ok(self)
+    }
+    /// Keys from a JWKS file (`{"keys": [...]}` with "RSA" and "oct" keys). The file is
+    /// read again when it changes so keys can be rotated without a restart.
+    pub fn with_jwks_file(mut self, path: impl AsRef<Path>) -> Result<JwtValidator, HttpServerError> {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified_time(&path);
+        let keys = load_jwks(&path)?;
+        self.jwks = Some(JwksFile {
+            path: path,
+            keys: RwLock::new(keys),
+            state: Mutex::new((modified, Instant::now())),
+        });
+        Ok(self)
+    }
+    pub fn with_issuer(mut self, issuer: &str) -> JwtValidator {
+        self.issuer = Some(issuer.to_owned());
+        self
+    }
+    pub fn with_audience(mut self, audience: &str) -> JwtValidator {
+        self.audience = Some(audience.to_owned());
+        self
+    }
+    pub fn with_leeway(mut self, leeway: Duration) -> JwtValidator {
+        self.leeway = leeway;
+        self
+    }
+
+    /// The claims of a valid token, 401 if the token is invalid
+    pub fn validate(&self, token: &str) -> Result<Value, HttpServerError> {
+        // header.payload is what is signed
+        let (signed, signature) = token.rsplit_once('.').ok_or_else(|| unauthorized("Malformed token!"))?;
+        let (header, payload) = signed
+            .split_once('.')
+            .filter(|(_, payload)| !payload.contains('.'))
+            .ok_or_else(|| unauthorized("Malformed token!"))?;
+        let header = decode_json(header)?;
+        let signature = decode_base64(signature)?;
+
+        if header.get("crit").is_some() {
+            return Err(unauthorized("Unsupported critical header!"));
+        }
+        let algorithm = header
+            .get("alg")
+            .and_then(|alg| alg.as_str())
+            .unwrap_or_default();
+        if algorithm != "HS256" && algorithm != "RS256" {
+            return Err(unauthorized(&format!("Unsupported algorithm {}!", algorithm)));
+        }
+        let kid = header.get("kid").and_then(|kid| kid.as_str());
+
+        self.reload_jwks();
+        if !self.verify(algorithm, kid, signed.as_bytes(), &signature) {
+            return Err(unauthorized("Invalid signature!"));
+        }
+
+        let claims = decode_json(payload)?;
+        self.check_claims(&claims)?;
+        Ok(claims)
+    }
+
+    fn verify(&self, algorithm: &str, kid: Option<&str>, signed: &[u8], signature: &[u8]) -> bool {
+        let jwks_keys = self.jwks.as_ref().and_then(|jwks| jwks.keys.read().ok());
+        self.keys
+            .iter()
+            .chain(jwks_keys.iter().flat_map(|keys| keys.iter()))
+            // Keys without a kid are tried for every token, every key for tokens without a kid
+            .filter(|key| kid.is_none() || key.kid.is_none() || key.kid.as_deref() == kid)
+            .any(|key| {
+                match (&key.material, algorithm) {
+                    (KeyMaterial::Hmac(secret), "HS256") => {
+                        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect(
+                            "HMAC accepts any key size"
+                        );
+                        mac.update(signed);
+                        mac.verify_slice(signature).is_ok()
+                    }
+                    (KeyMaterial::Rsa(public_key), "RS256") => {
+                        let hash = Sha256::digest(signed);
+                        public_key.verify(Pkcs1v15Sign::new::<Sha256>(), &hash, signature).is_ok()
+                    }
+                    // The algorithm must match the key type (no HS256 with an RSA public key)
+                    _ => false,
+                }
+            })
+    }
+
+    fn check_claims(&self, claims: &Value) -> Result<(), HttpServerError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or_default();
+        let leeway = self.leeway.as_secs_f64();
+
+        match claims.get("exp") {
+            Some(exp) => {
+                let exp = exp.as_f64().ok_or_else(|| unauthorized("Invalid exp claim!"))?;
+                if now > exp + leeway {
+                    return Err(unauthorized("Token expired!"));
+                }
+            }
+            None if self.require_exp => {
+                return Err(unauthorized("Missing exp claim!"));
+            }
+            None => {}
+        }
+        if let Some(nbf) = claims.get("nbf") {
+            let nbf = nbf.as_f64().ok_or_else(|| unauthorized("Invalid nbf claim!"))?;
+            if now + leeway < nbf {
+                return Err(unauthorized("Token not valid yet!"));
+            }
+        }
+        if let Some(issuer) = &self.issuer {
+            if claims.get("iss").and_then(|iss| iss.as_str()) != Some(issuer.as_str()) {
+                return Err(unauthorized("Invalid issuer!"));
+            }
+        }
+        if let Some(audience) = &self.audience {
+            // A string or an array of strings
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience.as_str())),
+                _ => false,
+            };
+            if !matches {
+                return Err(unauthorized("Invalid audience!"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the JWKS file again if it changed. A broken file keeps the old keys.
+    fn reload_jwks(&self) {
+        let jwks = match &self.jwks {
+            Some(jwks) => jwks,
+            None => {
+                return;
+            }
+        };
+        let mut state = match jwks.state.lock() {
+            Ok(state) => state,
+            Err(_) => {
+                return;
+            }
+        };
+        if state.1.elapsed() < JWKS_CHECK_INTERVAL {
+            return;
+        }
+        state.1 = Instant::now();
+        let modified = modified_time(&jwks.path);
+        if modified == state.0 {
+            return;
+        }
+        if let Ok(keys) = load_jwks(&jwks.path) {
+            if let Ok(mut current) = jwks.keys.write() {
+                *current = keys;
+                state.0 = modified;
+            }
+        }
+    }
+}
+
+impl Default for JwtValidator {
+    fn default() -> JwtValidator {
+        return JwtValidator::new();
+    }
+}
+
+fn load_jwks(path: &Path) -> Result<Vec<JwtKey>, HttpServerError> {
+    let invalid = |reason: &str| HttpServerError::new(&format!("Invalid JWKS file: {}", reason));
+    let content = fs::read_to_string(path).map_err(|e| invalid(&e.to_string()))?;
+    let jwks: Value = serde_json::from_str(&content).map_err(|e| invalid(&e.to_string()))?;
+    let keys = jwks
+        .get("keys")
+        .and_then(|keys| keys.as_array())
+        .ok_or_else(|| invalid("missing keys"))?;
+
+    let mut loaded = Vec::new();
+    for key in keys {
+        let field = |name: &str| key.get(name).and_then(|value| value.as_str());
+        // A missing or empty value is an error (an empty "k" would let anyone sign tokens)
+        let bytes = |name: &str| -> Result<Vec<u8>, HttpServerError> {
+            let value = field(name).ok_or_else(|| invalid(&format!("missing {}", name)))?;
+            URL_SAFE_NO_PAD
+                .decode(value.trim_end_matches('='))
+                .ok()
+                .filter(|bytes| !bytes.is_empty())
+                .ok_or_else(|| invalid(&format!("invalid {}", name)))
+        };
+        // Keys for signing only
+        if field("use").map_or(false, |usage| usage != "sig") {
+            continue;
+        }
+        let material = match (field("kty"), field("alg")) {
+            (Some("RSA"), None | Some("RS256")) => {
+                let public_key = RsaPublicKey::new(
+                    BigUint::from_bytes_be(&bytes("n")?),
+                    BigUint::from_bytes_be(&bytes("e")?)
+                ).map_err(|e| invalid(&e.to_string()))?;
+                KeyMaterial::Rsa(public_key)
+            }
+            (Some("oct"), None | Some("HS256")) => KeyMaterial::Hmac(bytes("k")?),
+            // Other key types and algorithms aren't supported
+            _ => {
+                continue;
+            }
+        };
+        loaded.push(JwtKey { kid: field("kid").map(|kid| kid.to_owned()), material: material });
+    }
+    Ok(loaded)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>, HttpServerError> {
+    URL_SAFE_NO_PAD.decode(input).map_err(|_| unauthorized("Malformed token!"))
+}
+
+fn decode_json(input: &str) -> Result<Value, HttpServerError> {
+    serde_json::from_slice(&decode_base64(input)?).map_err(|_| unauthorized("Malformed token!"))
+}
+
+fn unauthorized(reason: &str) -> HttpServerError {
+    HttpServerError::with_code(ResponseCode::Unauthorized, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"a secret that is long enough for the tests";
+    const RSA_PEM: &str =
+        "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEA6vaalxjnDXK3CuJ2kJ6M
+FXYFGMkELbcXBW0jC+jXqvjMhEq9/GDRdmgJTHGFeI2s0I85KjQsZIeRcROXKZWS
+D4VKtLW/rc4SSIoS0hCWoI8VLS2T7WwnYT8glY5Uz1X3BfO4Tw7H/77TFTKtm8pD
+ND6ahoHdBVmu/O0l5f/shYr2A55BPs1I9g3o7K9Id9g7+z+okBCjdS1dWINckXtW
+9tiSf4xTbE4qzKTHC/NAcIVjg2Z1MplgPvJ7IipGM3rvJ5I9lwg+Mx/5vNINJMBO
+Ieh1RnT+As1+nccLmRlClgTF+pqcA6KdmHtMTHauBQz/3JaKIlPw1JdbS9dvgi86
+owIDAQAB
+-----END PUBLIC KEY-----
+";
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn hs256(header: Value, claims: Value, secret: &[u8]) -> String {
+        let signed = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).unwrap();
+        mac.update(signed.as_bytes());
+        format!("{}.{}", signed, URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    fn token(claims: Value) -> String {
+        hs256(json!({ "alg": "HS256" }), claims, SECRET)
+    }
+
+    fn jwks_file(name: &str, jwks: Value) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("choki-{}-{}.json", name, std::process::id()));
+        fs::write(&path, jwks.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn valid_token() {
+        let validator = JwtValidator::new().with_hmac_secret(SECRET).unwrap();
+        let claims = validator.validate(&token(json!({ "sub": "alice", "exp": now() + 60 }))).unwrap();
+        assert_eq!(claims["sub"], "alice");
+        assert!(validator.validate(&token(json!({ "sub": "alice" }))).is_err());
+    }
+
+    #[test]
+    fn rejects_hs256_signed_with_the_rsa_key() {
+        let validator = JwtValidator::new().with_rsa_pem(RSA_PEM).unwrap();
+        let forged = hs256(json!({ "alg": "HS256" }), json!({ "exp": now() + 60 }), RSA_PEM.as_bytes());
+        assert!(validator.validate(&forged).is_err());
+        let none = format!(
+            "{}.{}.",
+            URL_SAFE_NO_PAD.encode(json!({ "alg": "none" }).to_string()),
+            URL_SAFE_NO_PAD.encode(json!({ "exp": now() + 60 }).to_string())
+        );
+        assert!(validator.validate(&none).is_err());
+    }
+
+    #[test]
+    fn checks_exp_nbf_and_aud() {
+        let validator = JwtValidator::new()
+            .with_hmac_secret(SECRET)
+            .unwrap()
+            .with_audience("api")
+            .with_leeway(Duration::from_secs(5));
+        let exp = now() + 60;
+        assert!(validator.validate(&token(json!({ "exp": exp, "aud": "api" }))).is_ok());
+        assert!(validator.validate(&token(json!({ "exp": exp, "aud": ["web", "api"] }))).is_ok());
+        assert!(validator.validate(&token(json!({ "exp": now() - 30, "aud": "api" }))).is_err());
+        assert!(validator.validate(&token(json!({ "exp": exp, "nbf": now() + 30, "aud": "api" }))).is_err());
+        assert!(validator.validate(&token(json!({ "exp": exp, "aud": "web" }))).is_err());
+        assert!(validator.validate(&token(json!({ "exp": exp }))).is_err());
+        assert!(validator.validate(&token(json!({ "exp": "soon", "aud": "api" }))).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_jwks_key() {
+        let path = jwks_file("empty-k", json!({ "keys": [{ "kty": "oct", "k": "" }] }));
+        assert!(JwtValidator::new().with_jwks_file(&path).is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn token_without_kid_tries_every_key() {
+        let k = URL_SAFE_NO_PAD.encode(SECRET);
+        let path = jwks_file("kid", json!({ "keys": [{ "kty": "oct", "kid": "2026", "k": k }] }));
+        let validator = JwtValidator::new().with_jwks_file(&path).unwrap();
+        let claims = json!({ "exp": now() + 60 });
+        assert!(validator.validate(&token(claims.clone())).is_ok());
+        assert!(validator.validate(&hs256(json!({ "alg": "HS256", "kid": "2026" }), claims.clone(), SECRET)).is_ok());
+        assert!(validator.validate(&hs256(json!({ "alg": "HS256", "kid": "2025" }), claims, SECRET)).is_err());
+        let _ = fs::remove_file(path);
+    }
+}
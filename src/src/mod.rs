@@ -1,4 +1,18 @@
+pub mod auth;
+pub mod cache;
+pub mod connections;
+#[cfg(feature = "secure-cookies")]
+pub mod cookies;
+pub mod cors;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod multipart;
+pub mod parser;
+pub mod proxy;
+pub mod rate_limit;
 pub mod request;
 pub mod response;
+pub mod session;
+pub mod static_files;
 pub mod structs;
 pub mod utils;
@@ -0,0 +1,492 @@
+use std::{
+    fs::{ self, File, OpenOptions },
+    io::{ self, Read, Write },
+    path::{ Path, PathBuf },
+    process,
+    sync::atomic::{ AtomicBool, AtomicU64, Ordering },
+    time::{ SystemTime, UNIX_EPOCH },
+};
+
+use base64::{ engine::general_purpose::STANDARD, Engine };
+
+use super::{
+    parser::parse_header_line,
+    structs::{ HeaderMap, HttpServerError, ResponseCode },
+    utils::decompress::read_error,
+};
+
+/// Max length of a single part header line
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+/// Max length of all the headers of a part
+const MAX_PART_HEADERS_SIZE: usize = 16 * 1024;
+const READ_SIZE: usize = 16 * 1024;
+
+/// Limits for multipart bodies. Sizes of 0 mean no limit (`max_content_length` still applies).
+#[derive(Clone, Debug)]
+pub struct MultipartLimits {
+    /// Max number of parts (413 if over)
+    pub max_parts: usize,
+    /// Max size of a part that is not a file in bytes (413 if over)
+    pub max_part_size: usize,
+    /// Max size of a file part in bytes (413 if over)
+    pub max_file_size: u64,
+    /// File parts bigger than this are written to a temporary file instead of memory
+    pub spool_threshold: Option<usize>,
+    /// Folder for the temporary files (the system temp folder if None)
+    pub spool_dir: Option<PathBuf>,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        return MultipartLimits {
+            max_parts: 100,
+            max_part_size: 0,
+            max_file_size: 0,
+            spool_threshold: None,
+            spool_dir: None,
+        };
+    }
+}
+
+/// An uploaded file written to disk. The file is deleted when this is dropped
+/// (with the request) unless it was moved away with `persist`.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    size: u64,
+    file_name: Option<String>,
+    file: Option<File>,
+    persisted: AtomicBool,
+}
+
+impl TempFile {
+    /// Creates an empty temporary file in the folder
+    pub fn new(dir: &Path, file_name: Option<String>) -> io::Result<TempFile> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        loop {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.subsec_nanos())
+                .unwrap_or_default();
+            let path = dir.join(
+                format!(
+                    "choki-{}-{}-{:08x}.upload",
+                    process::id(),
+                    COUNTER.fetch_add(1, Ordering::Relaxed),
+                    nanos
+                )
+            );
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            match options.open(&path) {
+                Ok(file) => {
+                    return Ok(TempFile {
+                        path: path,
+                        size: 0,
+                        file_name: file_name,
+                        file: Some(file),
+                        persisted: AtomicBool::new(false),
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Size in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    /// The file name sent by the client
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+    /// Opens the file for reading
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+    /// Moves the file so it is kept after the request (copies it if it can't be moved)
+    pub fn persist(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        if fs::rename(&self.path, path.as_ref()).is_ok() {
+            self.persisted.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        // Different filesystem
+        fs::copy(&self.path, path.as_ref())?;
+        Ok(())
+    }
+    pub(crate) fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_all(data)?;
+            self.size += data.len() as u64;
+        }
+        Ok(())
+    }
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted.load(Ordering::Relaxed) {
+            self.file = None;
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Receives the parts of a multipart body while it is parsed.
+pub trait MultipartSink {
+    /// A new part starts
+    fn part_start(&mut self, headers: HeaderMap) -> Result<(), HttpServerError>;
+    /// Data of the current part, called as many times as needed
+    fn part_data(&mut self, data: &[u8]) -> Result<(), HttpServerError>;
+    /// The current part ended
+    fn part_end(&mut self) -> Result<(), HttpServerError>;
+}
+
+/// Streaming multipart/form-data parser (RFC 7578, RFC 2046 5.1).
+///
+/// Parts are only split on "CRLF--boundary" so their data reaches the sink byte exact,
+/// without keeping the whole body in memory.
+pub struct MultipartParser<R: Read> {
+    reader: R,
+    delimiter: Vec<u8>,
+    window: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> MultipartParser<R> {
+    pub fn new(reader: R, boundary: &str) -> Result<MultipartParser<R>, HttpServerError> {
+        if !is_valid_boundary(boundary) {
+            return Err(bad_request("Invalid multipart boundary!"));
+        }
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        return Ok(MultipartParser {
+            reader: reader,
+            delimiter: delimiter,
+            // The first boundary can be at the very start without a line ending before it
+            window: b"\r\n".to_vec(),
+            eof: false,
+        });
+    }
+    /// Parses the whole body. The preamble and epilogue are ignored.
+    pub fn parse(&mut self, sink: &mut impl MultipartSink) -> Result<(), HttpServerError> {
+        self.skip_preamble()?;
+        loop {
+            // "--" after the boundary ends the body
+            self.ensure(2)?;
+            if self.window.starts_with(b"--") {
+                return Ok(());
+            }
+            self.skip_boundary_line()?;
+
+            let headers = self.read_part_headers()?;
+            sink.part_start(headers)?;
+            self.read_part_data(sink)?;
+            sink.part_end()?;
+        }
+    }
+
+    fn skip_preamble(&mut self) -> Result<(), HttpServerError> {
+        loop {
+            if let Some(index) = find(&self.window, &self.delimiter) {
+                self.window.drain(..index + self.delimiter.len());
+                return Ok(());
+            }
+            self.drain_safe();
+            if !self.fill()? {
+                return Err(bad_request("Multipart body without a boundary!"));
+            }
+        }
+    }
+    /// Skips the transport padding and the line ending after a boundary
+    fn skip_boundary_line(&mut self) -> Result<(), HttpServerError> {
+        loop {
+            self.ensure(1)?;
+            match self.window[0] {
+                b' ' | b'\t' => {
+                    self.window.remove(0);
+                }
+                b'\n' => {
+                    self.window.remove(0);
+                    return Ok(());
+                }
+                b'\r' => {
+                    self.ensure(2)?;
+                    if self.window[1] != b'\n' {
+                        return Err(bad_request("Invalid multipart boundary line!"));
+                    }
+                    self.window.drain(..2);
+                    return Ok(());
+                }
+                _ => {
+                    return Err(bad_request("Invalid multipart boundary line!"));
+                }
+            }
+        }
+    }
+    fn read_part_headers(&mut self) -> Result<HeaderMap, HttpServerError> {
+        let mut headers = HeaderMap::new();
+        let mut total_size = 0;
+
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(headers);
+            }
+            total_size += line.len();
+            if total_size > MAX_PART_HEADERS_SIZE {
+                return Err(bad_request("Multipart part headers are too large!"));
+            }
+            // Browsers send file names as raw utf-8
+            let line = String::from_utf8_lossy(&line);
+            let (name, value) = parse_header_line(&line)?;
+            headers.append(name, value);
+        }
+    }
+    /// Reads a line ending with LF (the CR before it is optional) without the line ending
+    fn read_line(&mut self) -> Result<Vec<u8>, HttpServerError> {
+        loop {
+            if let Some(index) = self.window.iter().position(|byte| *byte == b'\n') {
+                let mut line: Vec<u8> = self.window.drain(..index + 1).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if line.len() > MAX_PART_HEADER_SIZE {
+                    return Err(bad_request("Multipart part header is too large!"));
+                }
+                return Ok(line);
+            }
+            if self.window.len() > MAX_PART_HEADER_SIZE + 2 {
+                return Err(bad_request("Multipart part header is too large!"));
+            }
+            if !self.fill()? {
+                return Err(bad_request("Multipart body ended early!"));
+            }
+        }
+    }
+    fn read_part_data(&mut self, sink: &mut impl MultipartSink) -> Result<(), HttpServerError> {
+        loop {
+            if let Some(index) = find(&self.window, &self.delimiter) {
+                if index > 0 {
+                    sink.part_data(&self.window[..index])?;
+                }
+                self.window.drain(..index + self.delimiter.len());
+                return Ok(());
+            }
+            let safe = self.safe_len();
+            if safe > 0 {
+                sink.part_data(&self.window[..safe])?;
+                self.window.drain(..safe);
+            }
+            if !self.fill()? {
+                return Err(bad_request("Multipart body ended early!"));
+            }
+        }
+    }
+    /// Bytes that can't be the start of a delimiter
+    fn safe_len(&self) -> usize {
+        self.window.len().saturating_sub(self.delimiter.len() - 1)
+    }
+    fn drain_safe(&mut self) {
+        let safe = self.safe_len();
+        self.window.drain(..safe);
+    }
+    /// Makes sure the window has at least `size` bytes
+    fn ensure(&mut self, size: usize) -> Result<(), HttpServerError> {
+        while self.window.len() < size {
+            if !self.fill()? {
+                return Err(bad_request("Multipart body ended early!"));
+            }
+        }
+        Ok(())
+    }
+    /// Reads more of the body into the window. False at the end of the body.
+    fn fill(&mut self) -> Result<bool, HttpServerError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let start = self.window.len();
+        self.window.resize(start + READ_SIZE, 0);
+        loop {
+            match self.reader.read(&mut self.window[start..]) {
+                Ok(size) => {
+                    self.window.truncate(start + size);
+                    self.eof = size == 0;
+                    return Ok(size > 0);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.window.truncate(start);
+                    return Err(read_error(e));
+                }
+            }
+        }
+    }
+}
+
+/// Decodes part data sent with a Content-Transfer-Encoding (base64 or quoted-printable) as it
+/// arrives, so encoded parts can be spooled like the others.
+pub enum TransferDecoder {
+    /// Bytes of an incomplete 4 character group
+    Base64(Vec<u8>),
+    /// An escape or soft line break that continues in the next data
+    QuotedPrintable(Vec<u8>),
+}
+
+impl TransferDecoder {
+    /// None if the data doesn't need decoding (7bit, 8bit, binary or unknown)
+    pub fn new(encoding: &str) -> Option<TransferDecoder> {
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "base64" => Some(TransferDecoder::Base64(Vec::new())),
+            "quoted-printable" => Some(TransferDecoder::QuotedPrintable(Vec::new())),
+            _ => None,
+        }
+    }
+    /// Decodes what it can, the rest is kept for the next call
+    pub fn decode(&mut self, data: &[u8]) -> Result<Vec<u8>, HttpServerError> {
+        match self {
+            TransferDecoder::Base64(pending) => {
+                pending.extend(data.iter().filter(|byte| !byte.is_ascii_whitespace()));
+                let end = pending.len() - (pending.len() % 4);
+                let decoded = decode_base64(&pending[..end])?;
+                pending.drain(..end);
+                Ok(decoded)
+            }
+            TransferDecoder::QuotedPrintable(pending) => {
+                pending.extend_from_slice(data);
+                // "=" needs the next 2 bytes
+                let tail = pending.len().saturating_sub(2);
+                let end = pending[tail..]
+                    .iter()
+                    .position(|&byte| byte == b'=')
+                    .map_or(pending.len(), |index| tail + index);
+                let decoded = decode_quoted_printable(&pending[..end]);
+                pending.drain(..end);
+                Ok(decoded)
+            }
+        }
+    }
+    /// Decodes the end of the part
+    pub fn finish(self) -> Result<Vec<u8>, HttpServerError> {
+        match self {
+            TransferDecoder::Base64(pending) => decode_base64(&pending),
+            TransferDecoder::QuotedPrintable(pending) => Ok(decode_quoted_printable(&pending)),
+        }
+    }
+}
+
+fn decode_base64(data: &[u8]) -> Result<Vec<u8>, HttpServerError> {
+    STANDARD.decode(data).map_err(|_| bad_request("Invalid base64 in multipart part!"))
+}
+
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut res: Vec<u8> = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        if data[index] != b'=' {
+            res.push(data[index]);
+            index += 1;
+            continue;
+        }
+        // Soft line break
+        if data[index + 1..].starts_with(b"\r\n") {
+            index += 3;
+            continue;
+        }
+        if data[index + 1..].starts_with(b"\n") {
+            index += 2;
+            continue;
+        }
+        let hex = data
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                res.push(byte);
+                index += 3;
+            }
+            None => {
+                res.push(b'=');
+                index += 1;
+            }
+        }
+    }
+    res
+}
+
+/// 1 to 70 characters from RFC 2046 that don't end with a space
+fn is_valid_boundary(boundary: &str) -> bool {
+    !boundary.is_empty() &&
+        boundary.len() <= 70 &&
+        !boundary.ends_with(' ') &&
+        boundary.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&byte))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn bad_request(reason: &str) -> HttpServerError {
+    HttpServerError::with_code(ResponseCode::BadRequest, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransferDecoder;
+
+    fn decode_in_chunks(encoding: &str, data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut decoder = TransferDecoder::new(encoding).unwrap();
+        let mut decoded = Vec::new();
+        for part in data.chunks(chunk) {
+            decoded.extend(decoder.decode(part).unwrap());
+        }
+        decoded.extend(decoder.finish().unwrap());
+        decoded
+    }
+
+    #[test]
+    fn identity_encodings_need_no_decoder() {
+        for encoding in ["binary", "7bit", "8BIT", "x-unknown"] {
+            assert!(TransferDecoder::new(encoding).is_none());
+        }
+    }
+
+    #[test]
+    fn base64_across_chunks() {
+        let data = b"aGVsbG8g\r\nd29ybGQh\r\nIQ==";
+        for chunk in 1..data.len() {
+            assert_eq!(decode_in_chunks("base64", data, chunk), b"hello world!!");
+        }
+        let mut decoder = TransferDecoder::new("base64").unwrap();
+        assert!(decoder.decode(b"aGV$").is_err());
+    }
+
+    #[test]
+    fn quoted_printable_across_chunks() {
+        let data = b"caf=C3=A9 =\r\nlatte=3D=\nok";
+        for chunk in 1..data.len() {
+            assert_eq!(decode_in_chunks("quoted-printable", data, chunk), "café latte=ok".as_bytes());
+        }
+    }
+}
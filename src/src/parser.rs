@@ -0,0 +1,309 @@
+use std::io::{ BufRead, Read };
+
+use urlencoding::decode_binary;
+
+use super::{ structs::{ HttpServerError, ResponseCode }, utils::deadline::is_timeout };
+
+/// Limits applied while reading the request line and headers.
+#[derive(Clone, Copy, Debug)]
+pub struct HeaderLimits {
+    /// Max length of the request line (414 if over)
+    pub max_request_line: usize,
+    /// Max length of a single header line (431 if over)
+    pub max_header_size: usize,
+    /// Max length of all header lines together (431 if over)
+    pub max_headers_size: usize,
+    /// Max number of headers (431 if over)
+    pub max_header_count: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> HeaderLimits {
+        return HeaderLimits {
+            max_request_line: 8 * 1024,
+            max_header_size: 8 * 1024,
+            max_headers_size: 64 * 1024,
+            max_header_count: 100,
+        };
+    }
+}
+
+/// Reads the request line and the header lines (up to the empty line).
+/// The first line is the request line. Errors carry the code that should be sent back
+/// (400, 414 or 431) or none if the connection was closed.
+pub fn read_head(
+    reader: &mut impl BufRead,
+    limits: &HeaderLimits
+) -> Result<Vec<String>, HttpServerError> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut total_size = 0;
+
+    loop {
+        let is_request_line = lines.is_empty();
+        let max = if is_request_line { limits.max_request_line } else { limits.max_header_size };
+
+        let line = match read_line(reader, max)? {
+            Some(line) => line,
+            None => {
+                if is_request_line {
+                    return Err(
+                        HttpServerError::with_code(
+                            ResponseCode::UriTooLong,
+                            &format!("Request line is over {} bytes!", max)
+                        )
+                    );
+                }
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::RequestHeaderFieldsTooLarge,
+                        &format!("Header is over {} bytes!", max)
+                    )
+                );
+            }
+        };
+        if line.is_empty() {
+            // Empty lines before the request line should be ignored
+            if is_request_line {
+                continue;
+            }
+            break;
+        }
+
+        if !is_request_line {
+            total_size += line.len();
+            if total_size > limits.max_headers_size {
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::RequestHeaderFieldsTooLarge,
+                        &format!("Headers are over {} bytes!", limits.max_headers_size)
+                    )
+                );
+            }
+            // The first line is the request line
+            if lines.len() > limits.max_header_count {
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::RequestHeaderFieldsTooLarge,
+                        &format!("More than {} headers!", limits.max_header_count)
+                    )
+                );
+            }
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Reads a line ending with LF (the CR before it is optional) without the line ending.
+/// Returns None if it is longer than `max`.
+fn read_line(reader: &mut impl BufRead, max: usize) -> Result<Option<String>, HttpServerError> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    // +2 for the line ending
+    let size = Read::take(&mut *reader, (max as u64) + 2)
+        .read_until(b'\n', &mut buffer)
+        .map_err(|e| {
+            if is_timeout(&e) {
+                return HttpServerError::with_code(
+                    ResponseCode::RequestTimeout,
+                    "Timed out reading the headers!"
+                );
+            }
+            HttpServerError::new(&format!("Error reading request headers: {}", e))
+        })?;
+    if size == 0 {
+        return Err(HttpServerError::new("Connection closed before the headers!"));
+    }
+    if buffer.last() != Some(&b'\n') {
+        if buffer.len() > max {
+            return Ok(None);
+        }
+        return Err(HttpServerError::new("Connection closed in the middle of the headers!"));
+    }
+    buffer.pop();
+    if buffer.last() == Some(&b'\r') {
+        buffer.pop();
+    }
+    if buffer.len() > max {
+        return Ok(None);
+    }
+    if buffer.contains(&b'\r') || buffer.contains(&b'\0') {
+        return Err(bad_request("Invalid character in headers!"));
+    }
+    match String::from_utf8(buffer) {
+        Ok(line) => Ok(Some(line)),
+        Err(_) => Err(bad_request("Headers are not valid utf-8!")),
+    }
+}
+
+/// Splits a header line into name and value. The name must be a token directly followed
+/// by ':' and the value has its surrounding whitespace removed.
+/// Continuation lines (obs-fold) are rejected.
+pub fn parse_header_line(line: &str) -> Result<(&str, &str), HttpServerError> {
+    if line.starts_with([' ', '\t']) {
+        return Err(bad_request("Folded headers are not supported!"));
+    }
+    let (name, value) = match line.split_once(':') {
+        Some(parts) => parts,
+        None => {
+            return Err(bad_request("Header without a colon!"));
+        }
+    };
+    if !is_token(name) {
+        return Err(bad_request("Invalid header name!"));
+    }
+    Ok((name, value.trim_matches([' ', '\t'])))
+}
+
+/// Splits the request line into method, target and version.
+pub fn parse_request_line(line: &str) -> Result<(&str, &str, &str), HttpServerError> {
+    let parts: Vec<&str> = line.split([' ', '\t']).filter(|part| !part.is_empty()).collect();
+    if parts.len() != 3 {
+        return Err(bad_request("Invalid request line!"));
+    }
+    if !is_token(parts[0]) {
+        return Err(bad_request("Invalid method!"));
+    }
+    Ok((parts[0], parts[1], parts[2]))
+}
+
+/// True if the input is a non empty token (RFC 9110 5.6.2)
+pub fn is_token(input: &str) -> bool {
+    !input.is_empty() &&
+        input
+            .bytes()
+            .all(|byte| { byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte) })
+}
+
+/// Splits a header value with parameters (`form-data; name="a;b"; filename*=UTF-8''c.txt`)
+/// into the value and its parameters. Parameter names are lowercase and quoted values are unescaped.
+pub fn parse_header_params(input: &str) -> (String, Vec<(String, String)>) {
+    let (value, mut rest) = input.split_once(';').unwrap_or((input, ""));
+    let mut params: Vec<(String, String)> = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        if rest.is_empty() {
+            break;
+        }
+        let name_end = rest.find(['=', ';']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim().to_ascii_lowercase();
+        rest = &rest[name_end..];
+
+        let mut param_value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start_matches([' ', '\t']);
+            if let Some(quoted) = after.strip_prefix('"') {
+                // Quoted string with backslash escapes
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((index, char)) = chars.next() {
+                    match char {
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                param_value.push(escaped);
+                            }
+                        }
+                        '"' => {
+                            end = index + 1;
+                            break;
+                        }
+                        char => param_value.push(char),
+                    }
+                }
+                rest = &quoted[end..];
+                rest = &rest[rest.find(';').unwrap_or(rest.len())..];
+            } else {
+                let value_end = after.find(';').unwrap_or(after.len());
+                param_value = after[..value_end].trim().to_owned();
+                rest = &after[value_end..];
+            }
+        }
+        if !name.is_empty() {
+            params.push((name, param_value));
+        }
+    }
+    (value.trim().to_owned(), params)
+}
+
+/// Decodes an extended parameter value (RFC 8187, `filename*=UTF-8''%E2%82%AC.txt`).
+pub fn decode_ext_value(input: &str) -> Option<String> {
+    let mut parts = input.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let value = decode_binary(parts.next()?.as_bytes());
+
+    if charset.eq_ignore_ascii_case("utf-8") {
+        return String::from_utf8(value.into_owned()).ok();
+    }
+    if charset.eq_ignore_ascii_case("iso-8859-1") {
+        return Some(
+            value
+                .iter()
+                .map(|byte| *byte as char)
+                .collect()
+        );
+    }
+    None
+}
+
+/// Parses a query string ("a=1&tag=x&tag=y&flag") into decoded pairs, in order.
+/// Pairs are split before decoding so encoded '&' and '=' stay in the values,
+/// '+' is a space and keys without '=' (flags) get an empty value.
+pub fn parse_query(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_query_component(key), decode_query_component(value))
+        })
+        .collect()
+}
+
+/// Percent decodes a query key or value ('+' is a space). Invalid utf-8 is replaced.
+pub fn decode_query_component(input: &str) -> String {
+    let input = input.replace('+', " ");
+    String::from_utf8_lossy(&decode_binary(input.as_bytes())).into_owned()
+}
+
+fn bad_request(reason: &str) -> HttpServerError {
+    HttpServerError::with_code(ResponseCode::BadRequest, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ parse_header_line, parse_request_line };
+    use crate::src::{ request::Request, structs::ResponseCode };
+
+    #[test]
+    fn rejects_folded_headers() {
+        assert!(parse_header_line(" continued").is_err());
+        assert!(parse_header_line("\tcontinued").is_err());
+        assert_eq!(parse_header_line("Host:  example.com \t").unwrap(), ("Host", "example.com"));
+    }
+
+    #[test]
+    fn rejects_bad_tokens() {
+        assert!(parse_header_line("Bad Name: value").is_err());
+        assert!(parse_header_line("Name : value").is_err());
+        assert!(parse_header_line(": value").is_err());
+        assert!(parse_header_line("no colon").is_err());
+        assert!(parse_request_line("G(E)T / HTTP/1.1").is_err());
+        assert!(parse_request_line("GET / HTTP/1.1 extra").is_err());
+        assert_eq!(parse_request_line("GET /a HTTP/1.1").unwrap(), ("GET", "/a", "HTTP/1.1"));
+    }
+
+    #[test]
+    fn rejects_conflicting_content_length() {
+        let conflicting = vec!["Content-Length: 5", "Content-Length: 6"];
+        let error = Request::parse(&conflicting, None, None).err().unwrap();
+        assert_eq!(error.code, Some(ResponseCode::BadRequest));
+
+        let repeated = vec!["Content-Length: 5", "Content-Length: 5"];
+        assert_eq!(Request::parse(&repeated, None, None).unwrap().content_length, 5);
+
+        let signed = vec!["Content-Length: +5"];
+        assert!(Request::parse(&signed, None, None).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    io::{ self, BufRead },
+    net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr },
+    str::FromStr,
+};
+
+use super::{ parser::parse_header_params, structs::*, utils::deadline::is_timeout };
+
+/// An ip range in CIDR notation ("10.0.0.0/8", "fd00::/8") or a single address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, normalize(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - (self.prefix as u32)).unwrap_or(0);
+                (u32::from(net) & mask) == (u32::from(ip) & mask)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - (self.prefix as u32)).unwrap_or(0);
+                (u128::from(net) & mask) == (u128::from(ip) & mask)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = HttpServerError;
+
+    fn from_str(input: &str) -> Result<IpNet, HttpServerError> {
+        let invalid = || HttpServerError::new(&format!("Invalid ip range {}!", input));
+        let (addr, prefix) = match input.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (input.trim(), None),
+        };
+        let addr = normalize(addr.parse::<IpAddr>().map_err(|_| invalid())?);
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(IpNet { addr: addr, prefix: prefix })
+    }
+}
+
+/// Client info taken from the headers of a trusted proxy.
+#[derive(Debug, Default, PartialEq)]
+pub struct Forwarded {
+    pub ip: Option<String>,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+}
+
+/// Finds the client of a request that came through trusted proxies. Uses `Forwarded` (RFC 7239)
+/// or else `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`. The addresses are
+/// read from the right and the first one that isn't a trusted proxy is the client.
+/// Nothing is returned if `peer` isn't trusted.
+pub fn resolve_forwarded(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> Forwarded {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer) {
+        return Forwarded::default();
+    }
+
+    // One hop per entry: (for, proto, host)
+    let hops: Vec<(String, Option<String>, Option<String>)> = if headers.get("Forwarded").is_some() {
+        headers
+            .get_all("Forwarded")
+            .iter()
+            .flat_map(|value| split_list(value))
+            .map(|element| {
+                let (_, params) = parse_header_params(&format!(";{}", element));
+                let param = |name: &str| {
+                    params
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.clone())
+                };
+                (param("for").unwrap_or_default(), param("proto"), param("host"))
+            })
+            .collect()
+    } else {
+        let list = |name: &str| -> Vec<String> {
+            headers
+                .get_all(name)
+                .iter()
+                .flat_map(|value| split_list(value))
+                .collect()
+        };
+        let addresses = list("X-Forwarded-For");
+        let protos = list("X-Forwarded-Proto");
+        let hosts = list("X-Forwarded-Host");
+        // One per hop, otherwise the last one (set by the nearest proxy, the first can come from the client)
+        let pick = |values: &Vec<String>, index: usize| -> Option<String> {
+            if values.len() == addresses.len() {
+                return values.get(index).cloned();
+            }
+            values.last().cloned()
+        };
+        addresses
+            .iter()
+            .enumerate()
+            .map(|(index, address)| (address.clone(), pick(&protos, index), pick(&hosts, index)))
+            .collect()
+    };
+
+    let mut forwarded = Forwarded::default();
+    for (address, proto, host) in hops.into_iter().rev() {
+        forwarded.scheme = proto.map(|proto| proto.to_ascii_lowercase()).or(forwarded.scheme);
+        forwarded.host = host.or(forwarded.host);
+        match parse_node(&address) {
+            Some(ip) if is_trusted(&ip) => {
+                forwarded.ip = Some(ip.to_string());
+            }
+            Some(ip) => {
+                forwarded.ip = Some(ip.to_string());
+                break;
+            }
+            // "unknown" or an obfuscated name, nothing before it can be trusted
+            None => {
+                if !address.is_empty() {
+                    forwarded.ip = Some(address);
+                }
+                break;
+            }
+        }
+    }
+    forwarded
+}
+
+/// Reads a PROXY protocol (v1 or v2) header. Returns the client address, None if the proxy
+/// didn't send one (LOCAL or UNKNOWN).
+pub fn read_proxy_header<R: BufRead>(reader: &mut R) -> Result<Option<SocketAddr>, HttpServerError> {
+    const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+    let invalid = |reason: &str| {
+        HttpServerError::with_code(
+            ResponseCode::BadRequest,
+            &format!("Invalid PROXY protocol header: {}", reason)
+        )
+    };
+
+    // Both versions are longer than this
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start).map_err(read_error)?;
+
+    if start == V2_SIGNATURE {
+        let mut info = [0u8; 4];
+        reader.read_exact(&mut info).map_err(read_error)?;
+        let length = u16::from_be_bytes([info[2], info[3]]) as usize;
+        let mut data = vec![0u8; length];
+        reader.read_exact(&mut data).map_err(read_error)?;
+
+        if info[0] >> 4 != 2 {
+            return Err(invalid("unknown version"));
+        }
+        match info[0] & 0x0f {
+            // LOCAL, the proxy's own connection (health checks)
+            0 => {
+                return Ok(None);
+            }
+            // PROXY
+            1 => {}
+            _ => {
+                return Err(invalid("unknown command"));
+            }
+        }
+        return match info[1] >> 4 {
+            1 if data.len() >= 12 => {
+                let ip = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+                let port = u16::from_be_bytes([data[8], data[9]]);
+                Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+            }
+            2 if data.len() >= 36 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&data[..16]);
+                let port = u16::from_be_bytes([data[32], data[33]]);
+                Ok(Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port)))
+            }
+            1 | 2 => Err(invalid("address too short")),
+            // Unix sockets or unspecified
+            _ => Ok(None),
+        };
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing"));
+    }
+    // Max 107 bytes with the line ending
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= 107 {
+            return Err(invalid("line too long"));
+        }
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte).map_err(read_error)?;
+        line.push(byte[0]);
+    }
+    let line = String::from_utf8(line).map_err(|_| invalid("not text"))?;
+    let parts: Vec<&str> = line.trim_end().split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => {
+            let ip = source.parse::<IpAddr>().map_err(|_| invalid("source address"))?;
+            let port = port.parse::<u16>().map_err(|_| invalid("source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("unknown format")),
+    }
+}
+
+fn read_error(err: io::Error) -> HttpServerError {
+    if is_timeout(&err) {
+        return HttpServerError::with_code(
+            ResponseCode::RequestTimeout,
+            "Timed out reading the PROXY protocol header!"
+        );
+    }
+    HttpServerError::new(&format!("Error reading the PROXY protocol header: {}", err))
+}
+
+/// An ip from a forwarded header ("192.0.2.1", "192.0.2.1:80", "[2001:db8::1]:80")
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse::<IpAddr>().ok().map(normalize);
+    }
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(normalize(ip));
+    }
+    node.parse::<SocketAddr>()
+        .ok()
+        .map(|addr| normalize(addr.ip()))
+}
+
+/// IPv4 addresses mapped to IPv6 (::ffff:10.0.0.1) are compared as IPv4
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) =>
+            match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => IpAddr::V6(v6),
+            }
+        ip => ip,
+    }
+}
+
+/// Comma separated values, commas inside quotes are kept
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for char in value.chars() {
+        match char {
+            '"' => {
+                quoted = !quoted;
+                current.push(char);
+            }
+            ',' if !quoted => {
+                items.push(current.trim().to_owned());
+                current.clear();
+            }
+            char => current.push(char),
+        }
+    }
+    items.push(current.trim().to_owned());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(peer: &str, headers: &[(&str, &str)]) -> Forwarded {
+        let trusted = vec!["10.0.0.0/8".parse::<IpNet>().unwrap()];
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(name, value);
+        }
+        resolve_forwarded(peer.parse().unwrap(), &map, &trusted)
+    }
+
+    #[test]
+    fn untrusted_peer_is_ignored() {
+        let forwarded = resolve("192.0.2.1", &[("X-Forwarded-For", "198.51.100.1"), ("X-Forwarded-Host", "evil.com")]);
+        assert_eq!(forwarded, Forwarded::default());
+    }
+
+    #[test]
+    fn client_is_the_first_untrusted_from_the_right() {
+        let forwarded = resolve("10.0.0.1", &[("X-Forwarded-For", "203.0.113.9, 198.51.100.1, 10.0.0.2")]);
+        assert_eq!(forwarded.ip.as_deref(), Some("198.51.100.1"));
+    }
+
+    #[test]
+    fn spoofed_host_and_proto_without_xff() {
+        // The client sent the first values, the proxy appended its own
+        let forwarded = resolve("10.0.0.1", &[
+            ("X-Forwarded-For", "198.51.100.1"),
+            ("X-Forwarded-Host", "evil.com"),
+            ("X-Forwarded-Host", "example.com"),
+            ("X-Forwarded-Proto", "http, https"),
+        ]);
+        assert_eq!(forwarded.ip.as_deref(), Some("198.51.100.1"));
+        assert_eq!(forwarded.host.as_deref(), Some("example.com"));
+        assert_eq!(forwarded.scheme.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn spoofed_host_with_one_per_hop() {
+        let forwarded = resolve("10.0.0.1", &[
+            ("X-Forwarded-For", "203.0.113.9, 198.51.100.1"),
+            ("X-Forwarded-Host", "evil.com, example.com"),
+        ]);
+        assert_eq!(forwarded.ip.as_deref(), Some("198.51.100.1"));
+        assert_eq!(forwarded.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn spoofed_forwarded_elements() {
+        let forwarded = resolve("10.0.0.1", &[
+            ("Forwarded", "for=203.0.113.9;host=evil.com;proto=http"),
+            ("Forwarded", "for=198.51.100.1;host=example.com;proto=https, for=10.0.0.2"),
+        ]);
+        assert_eq!(forwarded.ip.as_deref(), Some("198.51.100.1"));
+        assert_eq!(forwarded.host.as_deref(), Some("example.com"));
+        assert_eq!(forwarded.scheme.as_deref(), Some("https"));
+    }
+
+    fn proxy_v2(command: u8) -> Vec<u8> {
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        header.extend_from_slice(&[0x20 | command, 0x11, 0, 12, 192, 0, 2, 1, 10, 0, 0, 1, 0x1f, 0x90, 0, 80]);
+        header
+    }
+
+    #[test]
+    fn proxy_v2_commands() {
+        let addr = read_proxy_header(&mut &proxy_v2(1)[..]).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:8080".parse().unwrap()));
+        assert_eq!(read_proxy_header(&mut &proxy_v2(0)[..]).unwrap(), None);
+        assert!(read_proxy_header(&mut &proxy_v2(2)[..]).is_err());
+        assert!(read_proxy_header(&mut &proxy_v2(0x0f)[..]).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    sync::{ atomic::{ AtomicU64, Ordering }, Mutex, MutexGuard },
+    time::{ Duration, Instant },
+};
+
+use super::{ request::Request, response::Response, structs::*, utils::utils::matches_prefix };
+
+/// How many requests a client can make. Works as a token bucket: a client can make
+/// `requests` requests at once and gets them back evenly over `period`.
+#[derive(Clone)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub period: Duration,
+    /// The client of a request (the ip by default), requests with None aren't limited
+    pub key: fn(req: &Request) -> Option<String>,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, period: Duration) -> RateLimit {
+        return RateLimit {
+            requests: requests.max(1),
+            period: period,
+            key: |req| req.ip.clone(),
+        };
+    }
+    /// Limits by something else than the ip (an api key, a user...)
+    pub fn with_key(mut self, key: fn(req: &Request) -> Option<String>) -> RateLimit {
+        self.key = key;
+        self
+    }
+    /// Tokens per second
+    fn rate(&self) -> f64 {
+        (self.requests as f64) / self.period.as_secs_f64().max(0.001)
+    }
+}
+
+/// The result of counting a request.
+pub struct RateLimitStatus {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the client has all its requests back
+    pub reset: u64,
+    /// Seconds until the next request is allowed (0 if allowed)
+    pub retry_after: u64,
+}
+
+impl RateLimitStatus {
+    /// Sets the RateLimit-* headers (and Retry-After if not allowed)
+    pub fn set_headers(&self, res: &mut Response) {
+        res.set_header(&Header::new("RateLimit-Limit", &self.limit.to_string()));
+        res.set_header(&Header::new("RateLimit-Remaining", &self.remaining.to_string()));
+        res.set_header(&Header::new("RateLimit-Reset", &self.reset.to_string()));
+        if !self.allowed {
+            res.set_header(&Header::new("Retry-After", &self.retry_after.to_string()));
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Rule {
+    prefix: String,
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+/// Rate limits by path prefix, the longest matching prefix is used.
+pub struct RateLimiter {
+    rules: Vec<Rule>,
+    checks: AtomicU64,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Vec<(String, RateLimit)>) -> RateLimiter {
+        let mut rules: Vec<Rule> = limits
+            .into_iter()
+            .map(|(prefix, limit)| Rule {
+                prefix: prefix,
+                limit: limit,
+                buckets: Mutex::new(HashMap::new()),
+            })
+            .collect();
+        rules.sort_by(|a, b| b.prefix.len().cmp(&a.prefix.len()));
+        return RateLimiter {
+            rules: rules,
+            checks: AtomicU64::new(0),
+        };
+    }
+    /// Counts the request, None if no limit applies to it
+    pub fn check(&self, path: &str, req: &Request) -> Option<RateLimitStatus> {
+        let rule = self.rules.iter().find(|rule| matches_prefix(&rule.prefix, path))?;
+        let key = (rule.limit.key)(req)?;
+        let now = Instant::now();
+        let capacity = rule.limit.requests as f64;
+        let rate = rule.limit.rate();
+
+        if self.checks.fetch_add(1, Ordering::Relaxed) % 1024 == 0 {
+            self.remove_full_buckets(now);
+        }
+        let mut buckets = rule.lock_buckets();
+        let bucket = buckets.entry(key).or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let retry_after = if allowed { 0 } else { ((1.0 - bucket.tokens) / rate).ceil().max(1.0) as u64 };
+        Some(RateLimitStatus {
+            allowed: allowed,
+            limit: rule.limit.requests,
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((capacity - bucket.tokens) / rate).ceil() as u64,
+            retry_after: retry_after,
+        })
+    }
+    /// Full buckets are the same as no bucket. Every rule is swept so the buckets
+    /// of rules that are rarely matched don't pile up.
+    fn remove_full_buckets(&self, now: Instant) {
+        for rule in &self.rules {
+            let capacity = rule.limit.requests as f64;
+            let rate = rule.limit.rate();
+            rule.lock_buckets().retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < capacity
+            });
+        }
+    }
+}
+
+impl Rule {
+    fn lock_buckets(&self) -> MutexGuard<'_, HashMap<String, Bucket>> {
+        // The buckets are only counters, a panic in another thread doesn't turn the limit off
+        self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(prefixes: &[&str], period: Duration) -> RateLimiter {
+        RateLimiter::new(
+            prefixes
+                .iter()
+                .map(|prefix| {
+                    let limit = RateLimit::new(1, period).with_key(|_| Some("client".to_owned()));
+                    (prefix.to_string(), limit)
+                })
+                .collect()
+        )
+    }
+
+    fn check(limiter: &RateLimiter, target: &str) -> Result<bool, HttpServerError> {
+        let url = Url::parse(&format!("GET {} HTTP/1.1", target))?;
+        let req = Request::parse(&Vec::new(), None, None)?;
+        Ok(limiter.check(&url.path, &req).map_or(true, |status| status.allowed))
+    }
+
+    #[test]
+    fn same_path_written_differently_shares_the_limit() {
+        let limiter = limiter(&["/files/private"], Duration::from_secs(60));
+        assert!(check(&limiter, "/files/private/secret.txt").unwrap());
+        assert!(!check(&limiter, "/files/./private/secret.txt").unwrap());
+        assert!(!check(&limiter, "/files/.%2Fprivate/secret.txt").unwrap());
+        assert!(!check(&limiter, "//files//private/secret.txt").unwrap());
+        assert!(check(&limiter, "/files/%5Cprivate/secret.txt").is_err());
+        assert!(check(&limiter, "/files/public/a.txt").unwrap());
+    }
+
+    #[test]
+    fn cleanup_sweeps_every_rule() {
+        let limiter = limiter(&["/a", "/b"], Duration::from_millis(1));
+        check(&limiter, "/b").unwrap();
+        assert_eq!(limiter.rules[1].lock_buckets().len(), 1);
+
+        std::thread::sleep(Duration::from_millis(5));
+        limiter.checks.store(1024, Ordering::Relaxed);
+        check(&limiter, "/a").unwrap();
+        assert_eq!(limiter.rules[1].lock_buckets().len(), 0);
+    }
+}
@@ -1,25 +1,70 @@
-use std::{ collections::HashMap, io::{ BufReader, Read, Write }, net::TcpStream };
-
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    io::{ self, BufReader, Read, Write },
+    net::TcpStream,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{ engine::general_purpose::STANDARD, Engine };
 use bumpalo::Bump;
 
 use crate::{ src::structs::*, Encoding };
 
-use super::utils::utils::{ replace_bytes, split_buffer_inxeses };
+use super::auth::Principal;
+#[cfg(feature = "secure-cookies")]
+use super::cookies::CookieKey;
+use super::multipart::{
+    TransferDecoder,
+    MultipartLimits,
+    MultipartParser,
+    MultipartSink,
+    TempFile,
+};
+use super::parser::{ parse_header_line, parse_header_params, parse_query };
+use super::session::Session;
+use super::utils::deadline::DeadlineReader;
+use super::utils::decompress::{ decoding_reader, read_error };
 
 pub struct Request {
     pub query: HashMap<String, String>,
     pub params: HashMap<String, String>,
-    pub headers: Vec<Header>,
+    pub headers: HeaderMap,
     pub cookies: Vec<Cookie>,
+    /// HTTP version from the request line
+    pub version: HttpVersion,
     // User data
+    /// Client ip (from the trusted proxy headers if it came through one)
     pub ip: Option<String>,
+    /// "http" or the scheme a trusted proxy got the request with
+    pub scheme: String,
+    /// Host header or the host a trusted proxy got the request for
+    pub host: Option<String>,
     pub user_agent: Option<String>,
     pub content_encoding: Option<Vec<Encoding>>,
     pub content_length: usize,
     // BODY
     pub content_type: Option<ContentType>,
     pub boudary: Option<String>,
-
+    /// Max time to read the whole body
+    pub body_timeout: Option<Duration>,
+    /// Limits for multipart bodies
+    pub multipart_limits: MultipartLimits,
+    /// Max size of a body sent with Content-Encoding after decoding it (0 for no limit)
+    pub max_decompressed_length: usize,
+    /// Key for signed and private cookies
+    #[cfg(feature = "secure-cookies")]
+    pub cookie_key: Option<Arc<CookieKey>>,
+    /// The session if sessions are enabled (`server.use_sessions`)
+    pub session: Option<Session>,
+    /// Who made the request on routes with authentication (`server.use_auth`)
+    pub principal: Option<Principal>,
+
+    query_string: String,
+    query_pairs: Vec<(String, String)>,
     body: Vec<BodyItemInfo>,
     body_data_segments: Vec<(usize, usize)>,
     buffer: Vec<u8>,
@@ -43,22 +88,35 @@ impl Request {
         return Request {
             query: query,
             params: params,
-            headers: headers,
+            headers: HeaderMap::from(headers),
             cookies: cookies,
+            version: HttpVersion::Http11,
 
             ip: ip,
+            scheme: "http".to_owned(),
+            host: None,
             user_agent: user_agent,
             content_encoding: content_encoding,
             content_length: content_length,
 
             content_type: content_type,
             boudary: boudary,
-
+            body_timeout: None,
+            multipart_limits: MultipartLimits::default(),
+            max_decompressed_length: 0,
+            #[cfg(feature = "secure-cookies")]
+            cookie_key: None,
+            session: None,
+            principal: None,
+
+            query_string: String::new(),
+            query_pairs: Vec::new(),
             body: Vec::new(),
             body_data_segments: Vec::new(),
             buffer: Vec::new(),
         };
     }
+    /// Parses the header lines (without the request line). Header names are case insensitive.
     pub fn parse(
         lines: &Vec<&str>,
         query: Option<HashMap<String, String>>,
@@ -76,92 +134,237 @@ impl Request {
             None,
             None
         );
-        fn extract_data(input: &str, skip_text: &str) -> String {
-            return input[skip_text.len()..].to_string();
-        }
-        for line in lines {
-            let lower_line = line.to_lowercase();
-            if lower_line.starts_with("user-agent:") {
-                let user_agent = extract_data(line, "User-Agent: ");
+        let mut content_length: Option<usize> = None;
 
-                req.user_agent = Some(user_agent);
-            } else if lower_line.starts_with("content-length:") {
-                let parts: Vec<&str> = line.split(" ").collect();
+        for line in lines {
+            let (name, value) = parse_header_line(line)?;
 
-                if parts.len() > 1 {
-                    req.content_length = match parts[1].parse::<usize>() {
-                        Ok(res) => res,
-                        Err(err) => 0,
+            match name.to_lowercase().as_str() {
+                "user-agent" => {
+                    req.user_agent = Some(value.to_owned());
+                }
+                "content-length" => {
+                    let length = match value.parse::<usize>() {
+                        Ok(length) if value.bytes().all(|byte| byte.is_ascii_digit()) => length,
+                        _ => {
+                            return Err(
+                                HttpServerError::with_code(
+                                    ResponseCode::BadRequest,
+                                    "Invalid Content-Length!"
+                                )
+                            );
+                        }
                     };
+                    if content_length.is_some() && content_length != Some(length) {
+                        return Err(
+                            HttpServerError::with_code(
+                                ResponseCode::BadRequest,
+                                "Conflicting Content-Length headers!"
+                            )
+                        );
+                    }
+                    content_length = Some(length);
                 }
-            } else if lower_line.starts_with("accept-encoding:") {
-                let content_encoding: String = extract_data(line, "Accept-Encoding: ");
-
-                let mut encodings: Vec<Encoding> = Vec::new();
-                let encodings_string: Vec<&str> = content_encoding.split(", ").collect();
-
-                let mut encoding: Encoding = Encoding::new(EncodingType::Unknown, -1.0);
-
-                for encoding_str in encodings_string {
-                    if encoding_str.contains(";") {
-                        let parts: Vec<&str> = encoding_str.split(";").collect();
-                        encoding.encoding_type = EncodingType::from_string(parts[0]);
-                        if parts.len() > 0 {
-                            encoding.encoding_type = EncodingType::from_string(parts[0]);
+                "accept-encoding" => {
+                    let mut encodings: Vec<Encoding> = Vec::new();
+
+                    for encoding_str in value.split(',') {
+                        let mut parts = encoding_str.split(';');
+                        let encoding_type = parts.next().unwrap_or_default().trim();
+                        if encoding_type.is_empty() {
+                            continue;
                         }
-                        if parts.len() > 1 {
-                            encoding.quality = match parts[1].parse::<f32>() {
-                                Ok(res) => res,
-                                Err(err) => -1.0,
-                            };
+                        let mut quality: f32 = -1.0;
+                        for param in parts {
+                            if let Some((key, value)) = param.split_once('=') {
+                                if key.trim().eq_ignore_ascii_case("q") {
+                                    quality = value.trim().parse::<f32>().unwrap_or(-1.0);
+                                }
+                            }
                         }
-                        encodings.push(encoding.clone());
-                    } else {
                         encodings.push(
-                            Encoding::new(EncodingType::from_string(encoding_str), -1.0)
+                            Encoding::new(EncodingType::from_string(encoding_type), quality)
                         );
                     }
+                    req.content_encoding = Some(encodings);
                 }
-                req.content_encoding = Some(encodings);
-            } else if lower_line.starts_with("content-type:") {
-                let content_type: String = extract_data(line, "Content-Type: ");
-                let parts: Vec<&str> = content_type.split("; ").collect();
-                if parts.len() > 0 {
-                    req.content_type = Some(ContentType::from_string(parts[0])?);
-                }
-                if parts.len() > 1 {
-                    let boudary = parts[1].replace("boundary=", "");
-                    req.boudary = Some(boudary);
-                }
-            } else if lower_line.starts_with("cookie:") {
-                let cookies_string = extract_data(line, "Cookie: ");
-                let cookies: Vec<&str> = cookies_string.split("; ").collect(); // not cookie cuz small chars
-
-                for cookie in cookies {
-                    let cookie_parts: Vec<&str> = cookie.split("=").collect();
-                    if cookie_parts.len() == 2 {
-                        req.cookies.push(
-                            Cookie::new_simple(
-                                cookie_parts[0].to_string(),
-                                cookie_parts[1].to_string()
-                            )
-                        );
+                "content-type" => {
+                    let (media_type, params) = parse_header_params(value);
+                    req.content_type = Some(
+                        ContentType::from_string(&media_type).map_err(|e|
+                            HttpServerError::with_code(ResponseCode::BadRequest, &e.reason)
+                        )?
+                    );
+                    for (name, value) in params {
+                        if name == "boundary" {
+                            req.boudary = Some(value);
+                        }
                     }
                 }
-            } else {
-                let mut parts: Vec<&str> = line.split(": ").collect();
-
-                if parts.len() > 1 {
-                    let key = parts[0].to_owned();
-                    parts.remove(0);
-                    let value = parts.join(": ");
-                    req.headers.push(Header::new(&key, &value));
+                "cookie" => {
+                    req.cookies.extend(Cookie::parse_header(value));
                 }
+                _ => {}
             }
+            req.headers.append(name, value);
         }
+        req.content_length = content_length.unwrap_or_default();
         return Ok(req);
     }
+    /// Gets a cookie sent by the client
+    pub fn get_cookie(&self, name: &str) -> Option<&Cookie> {
+        self.cookies.iter().find(|cookie| cookie.name == name)
+    }
+    /// User and password from `Authorization: Basic`
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, credentials) = self.headers.get("Authorization")?.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+        let (user, password) = credentials.split_once(':')?;
+        Some((user.to_owned(), password.to_owned()))
+    }
+    /// Token from `Authorization: Bearer`
+    pub fn bearer_token(&self) -> Option<&str> {
+        let (scheme, token) = self.headers.get("Authorization")?.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") || token.trim().is_empty() {
+            return None;
+        }
+        Some(token.trim())
+    }
+    /// Gets a signed cookie if its signature is valid (the value is without the signature)
+    #[cfg(feature = "secure-cookies")]
+    pub fn signed_cookie(&self, name: &str) -> Option<Cookie> {
+        self.cookie_key.as_ref()?.verify(self.get_cookie(name)?)
+    }
+    /// Gets a private cookie decrypted if it wasn't changed
+    #[cfg(feature = "secure-cookies")]
+    pub fn private_cookie(&self, name: &str) -> Option<Cookie> {
+        self.cookie_key.as_ref()?.decrypt(self.get_cookie(name)?)
+    }
+    /// Parses a route param ("/watch/[id]"). Fails with 400 Bad Request if it is missing or invalid.
+    pub fn param<F: FromStr>(&self, name: &str) -> Result<F, HttpServerError> where F::Err: Display {
+        let value = self.params
+            .get(name)
+            .ok_or_else(|| bad_request(&format!("Missing param \"{}\"!", name)))?;
+        value
+            .parse::<F>()
+            .map_err(|e| bad_request(&format!("Invalid param \"{}\": {}", name, e)))
+    }
+    // Query Stuff
+    /// Sets the query from the raw query string (after '?'). `query` gets the last value of every key.
+    pub fn set_query_string(&mut self, query_string: &str) {
+        self.query_pairs = parse_query(query_string);
+        self.query_string = query_string.to_owned();
+        self.query = self.query_pairs.iter().cloned().collect();
+    }
+    /// The raw query string (after '?'), not decoded
+    pub fn query_string(&self) -> &str {
+        &self.query_string
+    }
+    /// Every value of a repeated key in order (?tag=a&tag=b)
+    pub fn query_all(&self, key: &str) -> Vec<&str> {
+        self.query_pairs
+            .iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+    /// True if the key is in the query, with or without a value (?debug)
+    pub fn has_query(&self, key: &str) -> bool {
+        self.query_pairs.iter().any(|(name, _)| name == key)
+    }
+    /// Parses a single query value. Fails with 400 Bad Request if it is missing or invalid.
+    pub fn query_parsed<F: FromStr>(&self, key: &str) -> Result<F, HttpServerError>
+        where F::Err: Display
+    {
+        let value = self.query
+            .get(key)
+            .ok_or_else(|| bad_request(&format!("Missing query \"{}\"!", key)))?;
+        value
+            .parse::<F>()
+            .map_err(|e| bad_request(&format!("Invalid query \"{}\": {}", key, e)))
+    }
+    /// Deserializes the query into a struct. Fails with 400 Bad Request.
+    #[cfg(feature = "serde")]
+    pub fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpServerError> {
+        serde_urlencoded
+            ::from_str(&self.query_string)
+            .map_err(|e| bad_request(&format!("Invalid query: {}", e)))
+    }
     // Body Stuff
+    /// Deserializes the JSON body. Fails with 415 Unsupported Media Type if the body is not JSON
+    /// and with 400 Bad Request if it is malformed.
+    #[cfg(feature = "serde")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpServerError> {
+        match &self.content_type {
+            Some(content_type) if content_type.is_json() => {}
+            _ => {
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::UnsupportedMediaType,
+                        "Expected a JSON body (application/json)!"
+                    )
+                );
+            }
+        }
+        serde_json::from_slice(&self.buffer).map_err(|e| bad_request(&format!("Invalid JSON: {}", e)))
+    }
+    /// Form fields of an urlencoded or multipart body (files are skipped).
+    /// Repeated fields keep their last value.
+    pub fn form(&self) -> HashMap<String, String> {
+        self.form_pairs().into_iter().collect()
+    }
+    /// Every value of a repeated form field in order
+    pub fn form_all(&self, key: &str) -> Vec<String> {
+        self.form_pairs()
+            .into_iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .collect()
+    }
+    /// Deserializes the form fields into a struct. Fails with 415 Unsupported Media Type if the
+    /// body is not a form and with 400 Bad Request if the fields don't match.
+    #[cfg(feature = "serde")]
+    pub fn form_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpServerError> {
+        match &self.content_type {
+            Some(ContentType::UrlEncoded) | Some(ContentType::MultipartForm) => {}
+            _ => {
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::UnsupportedMediaType,
+                        "Expected a form body!"
+                    )
+                );
+            }
+        }
+        let encoded = serde_urlencoded
+            ::to_string(self.form_pairs())
+            .map_err(|e| bad_request(&format!("Invalid form: {}", e)))?;
+        serde_urlencoded
+            ::from_str(&encoded)
+            .map_err(|e| bad_request(&format!("Invalid form: {}", e)))
+    }
+    fn form_pairs(&self) -> Vec<(String, String)> {
+        let is_multipart = self.content_type == Some(ContentType::MultipartForm);
+        let mut pairs: Vec<(String, String)> = Vec::new();
+
+        for item in self.body() {
+            let name = match &item.info.name {
+                Some(name) => name.clone(),
+                None => {
+                    continue;
+                }
+            };
+            if item.info.content_type == ContentType::UrlEncoded {
+                pairs.push((name, item.value));
+            } else if is_multipart && item.info.file_name.is_none() {
+                pairs.push((name, String::from_utf8_lossy(item.data).into_owned()));
+            }
+        }
+        pairs
+    }
     pub fn body(&self) -> Vec<BodyItem> {
         if self.body.len() == 0 {
             return Vec::new();
@@ -195,126 +398,82 @@ impl Request {
         bfreader: &mut BufReader<TcpStream>,
         bump: Bump
     ) -> Result<bool, HttpServerError> {
-        let mut total_size = 0;
-
         let mut buffer: [u8; 4096] = [0; 4096];
 
         self.buffer = bump.alloc(Vec::new()).to_vec();
 
+        let mut reader = DeadlineReader::new(bfreader, self.body_timeout);
+        let mut body_reader: Box<dyn Read + '_> = Box::new(
+            Read::take(&mut reader, self.content_length as u64)
+        );
+        if let Some(content_encoding) = self.headers.get("Content-Encoding") {
+            body_reader = decoding_reader(
+                body_reader,
+                content_encoding,
+                self.max_decompressed_length
+            )?;
+        }
+
+        if self.content_type == Some(ContentType::MultipartForm) {
+            let boundary = match &self.boudary {
+                Some(boundary) => boundary.clone(),
+                None => {
+                    return Err(bad_request("Multipart body without a boundary!"));
+                }
+            };
+            let mut parser = MultipartParser::new(body_reader, &boundary)?;
+            let mut sink = BodySink {
+                body: &mut self.body,
+                segments: &mut self.body_data_segments,
+                buffer: &mut self.buffer,
+                limits: &self.multipart_limits,
+                start: 0,
+                size: 0,
+                is_file: false,
+                decoder: None,
+                temp_file: None,
+            };
+            parser.parse(&mut sink)?;
+            return Ok(true);
+        }
         loop {
-            match bfreader.read(&mut buffer) {
+            match body_reader.read(&mut buffer) {
+                Ok(0) => {
+                    break; // End of file
+                }
                 Ok(size) => {
-                    total_size += size;
-
                     self.buffer.extend_from_slice(&buffer[..size]);
-                    if size == 0 || total_size >= self.content_length {
-                        break; // End of file
-                    }
                 }
-                Err(_) => {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let err = read_error(e);
+                    if err.code.is_some() {
+                        return Err(err);
+                    }
                     break;
                 }
             }
         }
+        drop(body_reader);
 
         let content_type = self.content_type.as_ref().unwrap().clone();
         if content_type == ContentType::UrlEncoded {
-            let string_buffer = String::from_utf8_lossy(&self.buffer).to_string();
-
-            let parts: Vec<&str> = string_buffer.split("&").collect();
-
-            for part in parts {
-                let key_value: Option<(&str, &str)> = part.split_once("=");
-                if key_value.is_some() {
-                    let key_value = key_value.unwrap();
-
-                    let body_item = BodyItemInfo::new_url(
-                        key_value.0.to_owned(),
-                        key_value.1.to_owned()
-                    );
-
-                    self.body.push(body_item);
-                }
+            for (key, value) in parse_query(&String::from_utf8_lossy(&self.buffer)) {
+                self.body.push(BodyItemInfo::new_url(key, value));
             }
             return Ok(true);
         }
-        if content_type != ContentType::MultipartForm {
-            self.body.push(BodyItemInfo::new_simple(content_type));
-            self.body_data_segments.push((0, self.buffer.len()));
-            return Ok(true);
-        }
-        let boundary = (&self.boudary.clone().unwrap()).as_bytes().to_owned();
-
-        replace_bytes(&mut self.buffer, "\r\n--".as_bytes(), "".as_bytes());
-        replace_bytes(&mut self.buffer, "\r\nContent-Di".as_bytes(), "Content-Di".as_bytes());
-
-        let mut segments: Vec<(usize, usize)> = Vec::new();
-
-        for index in 0..self.buffer.len() {
-            let mut first_match: usize = usize::MAX;
-
-            let mut matches = 0;
-
-            if index + boundary.len() > self.buffer.len() {
-                break;
-            }
-
-            for index2 in index..index + boundary.len() {
-                if boundary[matches] == self.buffer[index2] {
-                    matches += 1;
-
-                    if first_match == usize::MAX {
-                        first_match = index2;
-                    }
-
-                    if matches == boundary.len() {
-                        if !segments.is_empty() {
-                            segments.last_mut().unwrap().1 = first_match;
-                        }
-
-                        segments.push((first_match + boundary.len(), 0));
-                        break;
-                    }
-                } else {
-                    break;
-                }
-            }
-        }
-
-        let mut data_indexes: Vec<(usize, usize)> = Vec::new();
-        let mut body_item_info: BodyItemInfo = BodyItemInfo::default();
-
-        for (start, end) in segments {
-            if start < end {
-                data_indexes = split_buffer_inxeses(
-                    &self.buffer[start..end],
-                    "\r\n\r\n".as_bytes()
-                );
-
-                body_item_info = BodyItemInfo::from_str(
-                    &String::from_utf8_lossy(
-                        &self.buffer[data_indexes[0].0 + start..data_indexes[0].1 + start]
-                    )
-                )?;
-                self.body_data_segments.push((
-                    data_indexes[1].0 + start,
-                    data_indexes[1].1 + start,
-                ));
-
-                self.body.push(body_item_info);
-
-                //Empty data after use
-                data_indexes.clear();
-            }
-        }
+        self.body.push(BodyItemInfo::new_simple(content_type));
+        self.body_data_segments.push((0, self.buffer.len()));
         return Ok(true);
     }
     pub fn read_only_body(&self, bfreader: &mut BufReader<TcpStream>) {
         let mut total_size = 0;
         let mut buffer: [u8; 4096] = [0; 4096];
 
+        let mut reader = DeadlineReader::new(bfreader, self.body_timeout);
         loop {
-            match bfreader.read(&mut buffer) {
+            match reader.read(&mut buffer) {
                 Ok(size) => {
                     total_size += size;
                     if size == 0 || total_size >= self.content_length {
@@ -328,3 +487,111 @@ impl Request {
         }
     }
 }
+
+/// Keeps multipart parts in the request buffer (or in temporary files)
+struct BodySink<'a> {
+    body: &'a mut Vec<BodyItemInfo>,
+    segments: &'a mut Vec<(usize, usize)>,
+    buffer: &'a mut Vec<u8>,
+    limits: &'a MultipartLimits,
+    start: usize,
+    size: u64,
+    is_file: bool,
+    decoder: Option<TransferDecoder>,
+    temp_file: Option<TempFile>,
+}
+
+impl BodySink<'_> {
+    /// Moves the data of the current part from memory to a temporary file
+    fn spool(&mut self) -> Result<(), HttpServerError> {
+        let dir = self.limits.spool_dir.clone().unwrap_or_else(env::temp_dir);
+        let file_name = self.body.last().and_then(|info| info.file_name.clone());
+
+        let mut temp_file = TempFile::new(&dir, file_name).map_err(spool_error)?;
+        temp_file.write(&self.buffer[self.start..]).map_err(spool_error)?;
+        self.buffer.truncate(self.start);
+        self.temp_file = Some(temp_file);
+        Ok(())
+    }
+    /// Adds decoded data to the current part, spooling file parts over the threshold
+    fn store(&mut self, data: &[u8]) -> Result<(), HttpServerError> {
+        if let Some(temp_file) = &mut self.temp_file {
+            return temp_file.write(data).map_err(spool_error);
+        }
+        self.buffer.extend_from_slice(data);
+        if let Some(threshold) = self.limits.spool_threshold {
+            if self.is_file && self.size > (threshold as u64) {
+                self.spool()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MultipartSink for BodySink<'_> {
+    fn part_start(&mut self, headers: HeaderMap) -> Result<(), HttpServerError> {
+        if self.limits.max_parts > 0 && self.body.len() >= self.limits.max_parts {
+            return Err(
+                HttpServerError::with_code(
+                    ResponseCode::ContentTooLarge,
+                    &format!("More than {} multipart parts!", self.limits.max_parts)
+                )
+            );
+        }
+        self.decoder = headers.get("Content-Transfer-Encoding").and_then(TransferDecoder::new);
+        let info = BodyItemInfo::from_headers(headers)?;
+        self.is_file = info.file_name.is_some();
+        self.body.push(info);
+        self.start = self.buffer.len();
+        self.size = 0;
+        Ok(())
+    }
+    fn part_data(&mut self, data: &[u8]) -> Result<(), HttpServerError> {
+        self.size += data.len() as u64;
+        let max = if self.is_file {
+            self.limits.max_file_size
+        } else {
+            self.limits.max_part_size as u64
+        };
+        if max > 0 && self.size > max {
+            return Err(
+                HttpServerError::with_code(
+                    ResponseCode::ContentTooLarge,
+                    &format!("Multipart part is over {} bytes!", max)
+                )
+            );
+        }
+
+        let decoded = match &mut self.decoder {
+            Some(decoder) => Some(decoder.decode(data)?),
+            None => None,
+        };
+        self.store(decoded.as_deref().unwrap_or(data))
+    }
+    fn part_end(&mut self) -> Result<(), HttpServerError> {
+        if let Some(decoder) = self.decoder.take() {
+            self.store(&decoder.finish()?)?;
+        }
+        if let Some(mut temp_file) = self.temp_file.take() {
+            temp_file.finish().map_err(spool_error)?;
+            if let Some(info) = self.body.last_mut() {
+                info.temp_file = Some(Arc::new(temp_file));
+            }
+            self.segments.push((self.start, self.start));
+            return Ok(());
+        }
+        self.segments.push((self.start, self.buffer.len()));
+        Ok(())
+    }
+}
+
+fn spool_error(err: io::Error) -> HttpServerError {
+    HttpServerError::with_code(
+        ResponseCode::InternalServerError,
+        &format!("Failed to write the upload to a temporary file: {}", err)
+    )
+}
+
+fn bad_request(reason: &str) -> HttpServerError {
+    HttpServerError::with_code(ResponseCode::BadRequest, reason)
+}
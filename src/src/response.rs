@@ -1,63 +1,165 @@
-use std::{ io::{ BufReader, Read, Write }, net::TcpStream, result };
+use std::{
+    fs::File,
+    io::{ self, BufReader, Read, Write },
+    net::{ Shutdown, TcpStream },
+    result,
+    sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+};
 
 use flate2::{ write::GzEncoder, Compression };
 
 use crate::{ src::structs::*, Encoding };
 
-use super::utils::utils::{ map_compression_level };
+#[cfg(feature = "secure-cookies")]
+use super::cookies::CookieKey;
+use super::session::Session;
+use super::utils::{ sendfile::send_file, utils::map_compression_level };
 
 pub struct Response {
     stream: TcpStream,
     status_code: ResponseCode,
 
     cookies: Vec<Cookie>,
-    headers: Vec<Header>,
+    headers: HeaderMap,
     content_encoding: Vec<Encoding>,
+    version: HttpVersion,
+    sent: Arc<AtomicBool>,
     pub use_compression: bool,
+    /// Key for signed and private cookies
+    #[cfg(feature = "secure-cookies")]
+    pub cookie_key: Option<Arc<CookieKey>>,
+    session: Option<Session>,
 }
 impl Response {
     pub fn new(stream: TcpStream, content_encoding: Option<Vec<Encoding>>) -> Response {
         return Response {
             stream: stream,
             cookies: Vec::new(),
-            headers: Vec::new(),
+            headers: HeaderMap::new(),
             content_encoding: content_encoding.unwrap_or_default(),
             use_compression: false,
+            version: HttpVersion::Http11,
+            sent: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "secure-cookies")]
+            cookie_key: None,
+            session: None,
             status_code: ResponseCode::Ok,
         };
     }
     /// Deletes a cookie
     pub fn delete_cookie(&mut self, name: &str) {
-        self.cookies.push(Cookie {
-            name: name.to_string(),
-            value: "".to_string(),
-            path: "/".to_string(),
-            expires: "Thu, 01 Jan 1970 00:00:00 GMT".to_string(),
-        });
+        let mut cookie = Cookie::new(name, "").with_path("/");
+        cookie.expires = "Thu, 01 Jan 1970 00:00:00 GMT".to_string();
+        cookie.max_age = Some(0);
+        self.cookies.push(cookie);
     }
     /// Creates/edits a cookie
     pub fn set_cookie(&mut self, cookie: &Cookie) {
         self.cookies.push(cookie.clone());
     }
-    /// Create Header
+    /// Creates/edits a signed cookie (the client can read but not change it)
+    #[cfg(feature = "secure-cookies")]
+    pub fn set_signed_cookie(&mut self, cookie: &Cookie) -> Result<(), HttpServerError> {
+        let signed = self.require_cookie_key()?.sign(cookie);
+        self.cookies.push(signed);
+        Ok(())
+    }
+    /// Creates/edits a private cookie (encrypted, the client can't read or change it)
+    #[cfg(feature = "secure-cookies")]
+    pub fn set_private_cookie(&mut self, cookie: &Cookie) -> Result<(), HttpServerError> {
+        let encrypted = self.require_cookie_key()?.encrypt(cookie)?;
+        self.cookies.push(encrypted);
+        Ok(())
+    }
+    #[cfg(feature = "secure-cookies")]
+    fn require_cookie_key(&self) -> Result<Arc<CookieKey>, HttpServerError> {
+        self.cookie_key
+            .clone()
+            .ok_or_else(||
+                HttpServerError::with_code(
+                    ResponseCode::InternalServerError,
+                    "No cookie key, use server.use_cookie_key!"
+                )
+            )
+    }
+    /// Creates/replaces a header (names are case insensitive)
     pub fn set_header(&mut self, header: &Header) {
-        self.headers.push(header.clone());
+        self.headers.insert(&header.name, &header.value);
+    }
+    /// Adds another value to a header without replacing the existing ones
+    pub fn append_header(&mut self, header: &Header) {
+        self.headers.append(&header.name, &header.value);
     }
-    /// Delete Header
+    /// Deletes every value of a header (names are case insensitive)
     pub fn delete_header(&mut self, name: &str) {
-        for i in 0..self.headers.len() {
-            if self.headers[i].name == name {
-                self.headers.remove(i);
-                break;
+        self.headers.remove(name);
+    }
+    /// Headers that will be sent
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    /// Sets the Content-Type header unless it was already set
+    fn set_content_type(&mut self, content_type: Option<&ContentType>) {
+        if let Some(content_type) = content_type {
+            if *content_type != ContentType::None && !self.headers.contains("Content-Type") {
+                self.headers.insert("Content-Type", &content_type.header_value());
             }
         }
     }
+    /// Sets the HTTP version of the request. HTTP/1.0 clients get bodies without
+    /// chunked transfer encoding and the connection is closed after the response.
+    pub fn set_version(&mut self, version: HttpVersion) {
+        self.version = version;
+    }
+    /// HTTP version of the request
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+    /// True once the status line and headers were written (writes to `get_stream` don't count)
+    pub fn is_sent(&self) -> bool {
+        self.sent.load(Ordering::Relaxed)
+    }
+    /// Shared flag telling if the response was sent, for checking it after the response is moved
+    pub(crate) fn sent_flag(&self) -> Arc<AtomicBool> {
+        self.sent.clone()
+    }
+    /// The session whose cookie is sent with the response when it changes
+    pub(crate) fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+    /// Status line, headers and cookies ending with the empty line.
+    /// The status line always says HTTP/1.1 (the highest version we support, RFC 9110 2.5).
+    /// Every response has `Connection: close` since the connection is closed after it.
+    fn head(&mut self) -> String {
+        self.sent.store(true, Ordering::Relaxed);
+        self.headers.insert("Connection", "close");
+        if let Some(cookie) = self.session.as_ref().and_then(|session| session.take_cookie()) {
+            self.cookies.push(cookie);
+        }
+        "HTTP/1.1 ".to_owned() +
+            &self.status_code.format_string() +
+            "\r\n" +
+            &self.headers.as_str() +
+            &Cookie::generate_set_cookie_headers(&self.cookies) +
+            "\r\n"
+    }
     /// Sets response status default it OK(200)
     pub fn set_status(&mut self, status_code: &ResponseCode) {
         self.status_code = *status_code;
     }
     // Encoding
 
+    /// True if the client accepts gzip encoded responses
+    pub fn accepts_gzip(&self) -> bool {
+        self.content_encoding
+            .iter()
+            .any(|encoding| {
+                (encoding.encoding_type == EncodingType::Gzip ||
+                    encoding.encoding_type == EncodingType::Any) &&
+                    encoding.quality != 0.0
+            })
+    }
+
     fn gzip_compress_data(&self, data: &[u8], compression_level: f32) -> Vec<u8> {
         let mut compression_level = compression_level;
         if compression_level == -1.0 {
@@ -109,6 +211,29 @@ impl Response {
     pub fn send_json(&mut self, data: &str) -> Result<(), HttpServerError> {
         self.send_bytes(&data.as_bytes(), Some(ContentType::Json))
     }
+    /// Serializes the value and sends it as JSON.
+    #[cfg(feature = "serde")]
+    pub fn json<S: serde::Serialize>(&mut self, value: &S) -> Result<(), HttpServerError> {
+        let data = serde_json
+            ::to_vec(value)
+            .map_err(|e|
+                HttpServerError::with_code(
+                    ResponseCode::InternalServerError,
+                    &format!("Failed to serialize JSON: {}", e)
+                )
+            )?;
+        self.send_bytes(&data, Some(ContentType::Json))
+    }
+    /// Serializes the value and sends it as JSON with a status code.
+    #[cfg(feature = "serde")]
+    pub fn json_with_status<S: serde::Serialize>(
+        &mut self,
+        status_code: ResponseCode,
+        value: &S
+    ) -> Result<(), HttpServerError> {
+        self.status_code = status_code;
+        self.json(value)
+    }
     /// Sends raw bytes.
     pub fn send_bytes(
         &mut self,
@@ -123,19 +248,9 @@ impl Response {
 
         let compressed_data = self.prepare_data(data);
 
-        self.headers.push(Header::new("Content-type", content_type.as_str()));
-        self.headers.push(Header::new("Content-Length", &compressed_data.len().to_string()));
-        let cookies_set_headers = Cookie::generate_set_cookie_headers(&self.cookies);
-
-        let headers_set_headers = Header::generate_headers(&self.headers);
-
-        let mut response =
-            "HTTP/1.1 ".to_owned() +
-            &self.status_code.format_string() +
-            &headers_set_headers +
-            &cookies_set_headers;
-        response = response.trim().to_owned();
-        response += "\r\n\r\n";
+        self.set_content_type(Some(&content_type));
+        self.headers.insert("Content-Length", &compressed_data.len().to_string());
+        let response = self.head();
 
         match self.stream.write_all(&response.as_bytes()) {
             Ok(_res) => {}
@@ -150,12 +265,15 @@ impl Response {
         }
         Ok(())
     }
-    /// Sends raw bytes in chunks.
+    /// Sends raw bytes in chunks (with Content-Length for HTTP/1.0).
     pub fn send_bytes_chunked(
         &mut self,
         data: &[u8],
         content_type: Option<ContentType>
     ) -> Result<(), HttpServerError> {
+        if self.version == HttpVersion::Http10 {
+            return self.send_bytes(data, content_type);
+        }
         let content_type: ContentType = if !content_type.is_none() {
             content_type.unwrap()
         } else {
@@ -164,22 +282,11 @@ impl Response {
 
         let compressed_data = self.prepare_data(data);
 
-        self.headers.push(Header::new("Content-type", content_type.as_str()));
-
-        self.headers.push(Header::new("Transfer-Encoding", "chunked"));
-        self.headers.push(Header::new("Connection", "keep-alive"));
+        self.set_content_type(Some(&content_type));
+        self.headers.remove("Content-Length");
+        self.headers.insert("Transfer-Encoding", "chunked");
 
-        let cookies_set_headers = Cookie::generate_set_cookie_headers(&self.cookies);
-
-        let headers_set_headers = Header::generate_headers(&self.headers);
-
-        let mut response =
-            "HTTP/1.1 ".to_owned() +
-            &self.status_code.format_string() +
-            &headers_set_headers +
-            &cookies_set_headers;
-        response = response.trim().to_owned();
-        response += "\r\n\r\n";
+        let response = self.head();
 
         match self.stream.write_all(&response.as_bytes()) {
             Ok(_res) => {}
@@ -221,44 +328,58 @@ impl Response {
         Ok(())
     }
     /// Pipe a whole stream. Aka read everything from input stream and send it.
+    /// If the size is known it is sent with Content-Length, otherwise with chunked transfer encoding
+    /// (HTTP/1.0 clients get the body until the connection is closed instead).
     pub fn pipe_stream(
         &mut self,
         mut stream: BufReader<impl Read>,
         content_type: Option<ContentType>,
         stream_size: Option<&u64>
     ) -> Result<(), HttpServerError> {
-        if let Some(ct) = content_type {
-            self.headers.push(Header::new("Content-Type", ct.as_str()));
-        }
+        self.set_content_type(content_type.as_ref());
         if let Some(ss) = stream_size {
-            self.headers.push(Header::new("Content-Length", &ss.to_string()));
+            self.headers.insert("Content-Length", &ss.to_string());
+            self.headers.remove("Transfer-Encoding");
+        } else if self.version == HttpVersion::Http10 {
+            self.headers.remove("Transfer-Encoding");
+            self.headers.remove("Content-Length");
+        } else {
+            self.headers.insert("Transfer-Encoding", "chunked");
+            self.headers.remove("Content-Length");
         }
-        self.headers.push(Header::new("Transfer-Encoding", "chunked"));
-        self.headers.push(Header::new("Connection", "keep-alive"));
 
-        let headers_set_headers = Header::generate_headers(&self.headers);
-        let cookies_set_headers = Cookie::generate_set_cookie_headers(&self.cookies);
-
-        let mut response =
-            "HTTP/1.1 ".to_owned() +
-            &self.status_code.format_string() +
-            &headers_set_headers +
-            &cookies_set_headers;
-        response = response.trim().to_owned();
-        response += "\r\n\r\n";
+        let response = self.head();
 
         if let Err(e) = self.stream.write_all(response.as_bytes()) {
             return Err(HttpServerError::new(&format!("Failed to write response headers: {}", e)));
         }
 
+        if let Some(stream_size) = stream_size {
+            let sent = io
+                ::copy(&mut stream.take(*stream_size), &mut self.stream)
+                .map_err(|e| HttpServerError::new(&format!("Failed to pipe stream: {}", e)))?;
+            if sent < *stream_size {
+                return Err(HttpServerError::new("Stream ended before its size!"));
+            }
+            if let Err(e) = self.stream.flush() {
+                return Err(HttpServerError::new(&format!("Failed to flush stream: {}", e)));
+            }
+            return Ok(());
+        }
+        if self.version == HttpVersion::Http10 {
+            // Close delimited body
+            io::copy(&mut stream, &mut self.stream).map_err(|e|
+                HttpServerError::new(&format!("Failed to pipe stream: {}", e))
+            )?;
+            if let Err(e) = self.stream.flush() {
+                return Err(HttpServerError::new(&format!("Failed to flush stream: {}", e)));
+            }
+            let _ = self.stream.shutdown(Shutdown::Write);
+            return Ok(());
+        }
+
         const CHUNK_SIZE: usize = 8192 * 2; // 16 KB chunk size
         let mut buffer = [0; CHUNK_SIZE];
-        let mut total_size: i64 = 0;
-        let stream_size: i64 = if stream_size.is_some() {
-            *stream_size.unwrap() as i64
-        } else {
-            -1
-        };
         loop {
             match stream.read(&mut buffer) {
                 Ok(0) => {
@@ -284,15 +405,11 @@ impl Response {
                             )
                         );
                     }
-                    total_size += n as i64;
                 }
                 Err(e) => {
                     return Err(HttpServerError::new(&format!("Failed to read from stream: {}", e)));
                 }
             }
-            if total_size > stream_size && stream_size > 0 {
-                break;
-            }
         }
 
         if let Err(e) = self.stream.write_all(b"0\r\n\r\n") {
@@ -304,15 +421,39 @@ impl Response {
         }
         Ok(())
     }
+    /// Sends `length` bytes of a file starting at `offset` with Content-Length.
+    /// On Linux the data is sent with sendfile (zero-copy), elsewhere it is copied.
+    pub fn send_file(
+        &mut self,
+        file: &File,
+        offset: u64,
+        length: u64,
+        content_type: Option<ContentType>
+    ) -> Result<(), HttpServerError> {
+        self.set_content_type(content_type.as_ref());
+        self.headers.insert("Content-Length", &length.to_string());
+        self.headers.remove("Transfer-Encoding");
+
+        let response = self.head();
+
+        if let Err(e) = self.stream.write_all(response.as_bytes()) {
+            return Err(HttpServerError::new(&format!("Failed to write response headers: {}", e)));
+        }
+        let sent = send_file(file, &self.stream, offset, length).map_err(|e|
+            HttpServerError::new(&format!("Failed to send file: {}", e))
+        )?;
+        if sent < length {
+            return Err(HttpServerError::new("File ended before its size!"));
+        }
+        Ok(())
+    }
     /// Send Download bytes.
     pub fn send_download_bytes(
         &mut self,
         data: &[u8],
         file_name: &str
     ) -> Result<(), HttpServerError> {
-        self.headers.push(
-            Header::new("Content-Disposition", &("attachment; filename=".to_string() + file_name))
-        );
+        self.headers.insert("Content-Disposition", &("attachment; filename=".to_string() + file_name));
         self.send_bytes_chunked(data, Some(ContentType::OctetStream))
     }
     /// Send Download stream.
@@ -322,25 +463,25 @@ impl Response {
         file_name: &str,
         file_size: Option<&u64>
     ) -> Result<(), HttpServerError> {
-        self.headers.push(
-            Header::new("Content-Disposition", &("attachment; filename=".to_string() + file_name))
-        );
+        self.headers.insert("Content-Disposition", &("attachment; filename=".to_string() + file_name));
 
         self.pipe_stream(stream, Some(ContentType::OctetStream), file_size)
     }
 
     //./ Sends a response code (404, 200...)
     pub fn send_code(&mut self, code: ResponseCode) -> Result<(), HttpServerError> {
-        let mut response = "HTTP/1.1 ".to_owned() + &code.format_string();
+        self.status_code = code;
+        // These can't have a body
+        let has_body = code != ResponseCode::NotModified && code != ResponseCode::NoContent;
 
-        self.set_header(&Header::new("Content-Type", "text/plain"));
-        self.set_header(&Header::new("Content-Length", &code.to_desc().len().to_string()));
-        let cookies_set_headers = Cookie::generate_set_cookie_headers(&self.cookies);
-        let headers_set_headers = Header::generate_headers(&self.headers);
-        response += &headers_set_headers;
-        response += &cookies_set_headers;
-
-        response += &("\r\n\r\n".to_owned() + &code.to_desc());
+        if has_body {
+            self.headers.insert("Content-Type", "text/plain");
+            self.headers.insert("Content-Length", &code.to_desc().len().to_string());
+        }
+        let mut response = self.head();
+        if has_body {
+            response += &code.to_desc();
+        }
         match self.stream.write_all(response.as_bytes()) {
             Ok(_res) => {}
             Err(_e) => {}
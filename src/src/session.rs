@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::{ atomic::{ AtomicU64, Ordering }, Arc, Mutex },
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
+};
+
+use urlencoding::encode;
+
+use super::{
+    parser::parse_query,
+    structs::{ Cookie, HttpServerError, ResponseCode, SameSite },
+};
+
+pub type SessionData = HashMap<String, String>;
+
+/// Makes the temporary file names of `FileStore::save` unique
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Where sessions are kept. Implement it to use your own backend (database, redis...).
+pub trait SessionStore: Send + Sync {
+    /// The data of a session that exists and didn't expire
+    fn load(&self, id: &str) -> Option<SessionData>;
+    /// Creates or replaces a session that expires after `ttl`
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), HttpServerError>;
+    /// Deletes a session
+    fn destroy(&self, id: &str) -> Result<(), HttpServerError>;
+}
+
+/// Session cookie and expiry settings.
+#[derive(Clone)]
+pub struct SessionConfig {
+    /// Sessions expire after this long without changes
+    pub ttl: Duration,
+    /// The session id cookie (name and attributes, the value and Max-Age are set for you)
+    pub cookie: Cookie,
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        return SessionConfig {
+            ttl: Duration::from_secs(24 * 60 * 60),
+            cookie: Cookie::new("session_id", "")
+                .with_path("/")
+                .with_http_only(true)
+                .with_same_site(SameSite::Lax),
+        };
+    }
+}
+
+/// The session of a request. Changes are saved to the store right away and the session
+/// cookie is sent with the response when something changed. Nothing is stored until a value is set.
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+    store: Arc<dyn SessionStore>,
+    config: Arc<SessionConfig>,
+}
+
+struct SessionState {
+    id: Option<String>,
+    data: SessionData,
+    cookie_changed: bool,
+}
+
+impl Session {
+    /// Loads the session from the id in the cookie (a new empty session if it is invalid or expired)
+    pub fn load(
+        store: Arc<dyn SessionStore>,
+        config: Arc<SessionConfig>,
+        id: Option<&str>
+    ) -> Session {
+        let loaded = id
+            .filter(|id| is_valid_id(id))
+            .and_then(|id| Some((id.to_owned(), store.load(id)?)));
+        let (id, data) = match loaded {
+            Some((id, data)) => (Some(id), data),
+            None => (None, SessionData::new()),
+        };
+        return Session {
+            state: Arc::new(
+                Mutex::new(SessionState {
+                    id: id,
+                    data: data,
+                    cookie_changed: false,
+                })
+            ),
+            store: store,
+            config: config,
+        };
+    }
+    /// The session id, None until something is stored
+    pub fn id(&self) -> Option<String> {
+        self.lock().ok()?.id.clone()
+    }
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.lock().ok()?.data.get(key).cloned()
+    }
+    pub fn get_parsed<F: FromStr>(&self, key: &str) -> Option<F> {
+        self.get(key)?.parse::<F>().ok()
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.lock()
+            .map(|state| state.data.contains_key(key))
+            .unwrap_or_default()
+    }
+    /// Sets a value, creating the session if needed
+    pub fn set(&self, key: &str, value: &str) -> Result<(), HttpServerError> {
+        let mut state = self.lock()?;
+        state.data.insert(key.to_owned(), value.to_owned());
+        self.save(&mut state)
+    }
+    pub fn remove(&self, key: &str) -> Result<(), HttpServerError> {
+        let mut state = self.lock()?;
+        if state.data.remove(key).is_none() || state.id.is_none() {
+            return Ok(());
+        }
+        self.save(&mut state)
+    }
+    /// Moves the session to a new id and deletes the old one. Call it on login so an id
+    /// known before (session fixation) can't be used.
+    pub fn regenerate(&self) -> Result<(), HttpServerError> {
+        let mut state = self.lock()?;
+        if let Some(old_id) = state.id.take() {
+            self.store.destroy(&old_id)?;
+        }
+        self.save(&mut state)
+    }
+    /// Deletes the session and its cookie (logout)
+    pub fn destroy(&self) -> Result<(), HttpServerError> {
+        let mut state = self.lock()?;
+        state.data.clear();
+        if let Some(id) = state.id.take() {
+            self.store.destroy(&id)?;
+            state.cookie_changed = true;
+        }
+        Ok(())
+    }
+    /// The Set-Cookie to send if the session changed since the last call
+    pub(crate) fn take_cookie(&self) -> Option<Cookie> {
+        let mut state = self.lock().ok()?;
+        if !state.cookie_changed {
+            return None;
+        }
+        state.cookie_changed = false;
+
+        let mut cookie = self.config.cookie.clone();
+        match &state.id {
+            Some(id) => {
+                cookie.value = id.clone();
+                cookie.max_age = Some(self.config.ttl.as_secs() as i64);
+            }
+            None => {
+                cookie.value = String::new();
+                cookie.max_age = Some(0);
+            }
+        }
+        Some(cookie)
+    }
+
+    fn save(&self, state: &mut SessionState) -> Result<(), HttpServerError> {
+        if state.id.is_none() {
+            state.id = Some(generate_id()?);
+        }
+        // Sent again so the cookie expires with the session
+        state.cookie_changed = true;
+        let id = state.id.clone().unwrap_or_default();
+        self.store.save(&id, &state.data, self.config.ttl)
+    }
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, SessionState>, HttpServerError> {
+        self.state.lock().map_err(|_| HttpServerError::new("Session lock poisoned!"))
+    }
+}
+
+/// Sessions in memory, lost on restart. Expired sessions are removed from time to time.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    saves: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        return MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            saves: AtomicU64::new(0),
+        };
+    }
+    pub fn len(&self) -> usize {
+        self.sessions
+            .lock()
+            .map(|sessions| sessions.len())
+            .unwrap_or_default()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        return MemoryStore::new();
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let mut sessions = self.sessions.lock().ok()?;
+        let (data, expires) = sessions.get(id)?;
+        if *expires <= Instant::now() {
+            sessions.remove(id);
+            return None;
+        }
+        Some(data.clone())
+    }
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), HttpServerError> {
+        let mut sessions = self.sessions
+            .lock()
+            .map_err(|_| HttpServerError::new("Session store lock poisoned!"))?;
+        let now = Instant::now();
+        if self.saves.fetch_add(1, Ordering::Relaxed) % 256 == 0 {
+            sessions.retain(|_, (_, expires)| *expires > now);
+        }
+        sessions.insert(id.to_owned(), (data.clone(), now + ttl));
+        Ok(())
+    }
+    fn destroy(&self, id: &str) -> Result<(), HttpServerError> {
+        if let Ok(mut sessions) = self.sessions.lock() {
+            sessions.remove(id);
+        }
+        Ok(())
+    }
+}
+
+/// Sessions as files in a folder (one file per session), kept across restarts.
+/// Expired files are deleted when they are loaded or with `remove_expired`.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates the folder if it doesn't exist
+    pub fn new(dir: impl Into<PathBuf>) -> Result<FileStore, HttpServerError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e|
+            HttpServerError::new(&format!("Failed to create session folder: {}", e))
+        )?;
+        Ok(FileStore { dir: dir })
+    }
+    /// Deletes every expired session file
+    pub fn remove_expired(&self) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_valid_id(&name) {
+                self.load(&name);
+            }
+        }
+    }
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        if !is_valid_id(id) {
+            return None;
+        }
+        let content = fs::read_to_string(self.path(id)).ok()?;
+        // First line is the expiry time, second the data
+        let (expires, data) = content.split_once('\n').unwrap_or((&content, ""));
+        let expires = expires.parse::<u64>().ok()?;
+        if expires <= unix_now() {
+            let _ = fs::remove_file(self.path(id));
+            return None;
+        }
+        Some(parse_query(data).into_iter().collect())
+    }
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> Result<(), HttpServerError> {
+        if !is_valid_id(id) {
+            return Err(HttpServerError::new("Invalid session id!"));
+        }
+        let data: Vec<String> = data
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+            .collect();
+        let content = format!("{}\n{}", unix_now() + ttl.as_secs(), data.join("&"));
+
+        // Written to another file first so a session is never half written,
+        // the name is unique so concurrent saves of the same session don't share it
+        let temp_path = self.dir.join(
+            format!(
+                "{}.{}.{}.tmp",
+                id,
+                process::id(),
+                TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            )
+        );
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .and_then(|_| fs::rename(&temp_path, self.path(id)));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(
+                HttpServerError::with_code(
+                    ResponseCode::InternalServerError,
+                    &format!("Failed to save session: {}", e)
+                )
+            );
+        }
+        Ok(())
+    }
+    fn destroy(&self, id: &str) -> Result<(), HttpServerError> {
+        if is_valid_id(id) {
+            let _ = fs::remove_file(self.path(id));
+        }
+        Ok(())
+    }
+}
+
+/// 32 random bytes as hex
+fn generate_id() -> Result<String, HttpServerError> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e|
+        HttpServerError::with_code(
+            ResponseCode::InternalServerError,
+            &format!("Failed to generate session id: {}", e)
+        )
+    )?;
+    Ok(
+        bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    )
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::{ hash_map::DefaultHasher, HashMap },
+    fs::File,
+    hash::Hasher,
+    io::{ BufRead, BufReader, Read },
+    path::{ Path, PathBuf },
+    sync::{ Arc, OnceLock },
+    time::UNIX_EPOCH,
+};
+
+use super::{
+    cache::{ gzip, CachedFile, StaticCache },
+    request::Request,
+    response::Response,
+    structs::*,
+    utils::{ mime::GZIP_EXTENSIONS, utils::normalize_segments },
+};
+
+/// Options shared by all static endpoints of a server.
+#[derive(Clone)]
+pub struct StaticOptions {
+    pub follow_symlinks: bool,
+    pub mime_types: HashMap<String, ContentType>,
+    pub sniff_content_type: bool,
+    pub compress: bool,
+    pub cache: Option<Arc<StaticCache>>,
+}
+
+impl StaticOptions {
+    /// Content type by extension, checking the user overrides first.
+    pub fn content_type(&self, path: &Path) -> Option<ContentType> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if let Some(content_type) = self.mime_types.get(&extension) {
+            return Some(content_type.clone());
+        }
+        ContentType::from_extension(&extension)
+    }
+}
+
+/// Resolves the part of the url after the mount path to a file inside `root`.
+///
+/// The input is expected to be already percent-decoded and is split with `normalize_segments`,
+/// so "..", '\' and NUL bytes reject the whole path (and ':' on Windows, drive letters and streams).
+/// The result is canonicalised and, if `follow_symlinks` is false, it must still be under `root`.
+/// `root` must be canonical.
+pub fn resolve_path(root: &Path, rest: &str, follow_symlinks: bool) -> Option<PathBuf> {
+    let segments = normalize_segments(rest)?;
+    if segments.is_empty() {
+        return None;
+    }
+    let mut path = root.to_path_buf();
+    for segment in segments {
+        #[cfg(windows)]
+        if segment.contains(':') {
+            return None;
+        }
+        path.push(segment);
+    }
+
+    let canonical = path.canonicalize().ok()?;
+    if !follow_symlinks && !canonical.starts_with(root) {
+        return None;
+    }
+    Some(canonical)
+}
+
+/// True for files stored gzip compressed (.svgz). They are sent as they are with
+/// `Content-Encoding: gzip` and never compressed again.
+pub fn is_gzipped(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| GZIP_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// A file compiled into the binary.
+pub struct EmbeddedFile {
+    pub data: &'static [u8],
+    pub content_type: Option<ContentType>,
+    etag: String,
+    gzip: OnceLock<Vec<u8>>,
+}
+
+/// In-memory files served by `Server::new_embedded`, keyed by their path relative to the mount
+/// (for example "css/main.css"). Use the `embed_assets!` macro to build it from `include_bytes!`.
+#[derive(Default)]
+pub struct EmbeddedAssets {
+    files: HashMap<String, EmbeddedFile>,
+}
+
+impl EmbeddedAssets {
+    pub fn new() -> EmbeddedAssets {
+        return EmbeddedAssets { files: HashMap::new() };
+    }
+    /// Adds a file. The content type is taken from the extension of the path.
+    pub fn add(&mut self, path: &str, data: &'static [u8]) -> &mut EmbeddedAssets {
+        let path = path.trim_start_matches('/');
+        let content_type = Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ContentType::from_extension);
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(data);
+        let etag = format!("\"{:x}-{:x}\"", data.len(), hasher.finish());
+
+        self.files.insert(path.to_owned(), EmbeddedFile {
+            data: data,
+            content_type: content_type,
+            etag: etag,
+            gzip: OnceLock::new(),
+        });
+        self
+    }
+    pub fn get(&self, path: &str) -> Option<&EmbeddedFile> {
+        self.files.get(path.trim_start_matches('/'))
+    }
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Builds `EmbeddedAssets` from files included in the binary. The file paths are relative to the
+/// file the macro is called in, like `include_bytes!`.
+///
+/// ```ignore
+/// let assets = choki::embed_assets! {
+///     "index.html" => "../static/index.html",
+///     "css/main.css" => "../static/css/main.css",
+/// };
+/// server.new_embedded("/", assets).unwrap();
+/// ```
+#[macro_export]
+macro_rules! embed_assets {
+    ($($path:expr => $file:expr),* $(,)?) => {
+        {
+            let mut assets = $crate::src::static_files::EmbeddedAssets::new();
+            $(
+                assets.add($path, include_bytes!($file));
+            )*
+            assets
+        }
+    };
+}
+
+/// Serves a file from a static endpoint. Sends 404 if the path can't be resolved to a file.
+pub fn serve_static(
+    route: &StaticEndpoint,
+    rest: &str,
+    options: &StaticOptions,
+    req: &Request,
+    res: &mut Response
+) -> Result<(), HttpServerError> {
+    match &route.source {
+        StaticSource::Folder(root) => serve_file(route, root, rest, options, req, res),
+        StaticSource::Embedded(assets) => {
+            let file = normalize_segments(rest).and_then(|segments| assets.get(&segments.join("/")));
+            match file {
+                Some(file) => {
+                    let path = Path::new(rest);
+                    let content_type = options.content_type(path).or_else(|| file.content_type.clone());
+                    let compress = !is_gzipped(path) && should_compress(options, content_type.as_ref());
+                    if is_gzipped(path) {
+                        res.set_header(&Header::new("Content-Encoding", &EncodingType::Gzip.to_string()));
+                    }
+                    send_static_bytes(
+                        file.data,
+                        || file.gzip.get_or_init(|| gzip(file.data)),
+                        content_type,
+                        &file.etag,
+                        compress,
+                        req,
+                        res
+                    )
+                }
+                None => res.send_code(ResponseCode::NotFound),
+            }
+        }
+    }
+}
+
+fn serve_file(
+    route: &StaticEndpoint,
+    root: &Path,
+    rest: &str,
+    options: &StaticOptions,
+    req: &Request,
+    res: &mut Response
+) -> Result<(), HttpServerError> {
+    let cache_key = match normalize_segments(rest) {
+        Some(segments) => format!("{}/{}", route.path.trim_end_matches('/'), segments.join("/")),
+        None => {
+            return res.send_code(ResponseCode::NotFound);
+        }
+    };
+    if let Some(cache) = &options.cache {
+        if let Some(entry) = cache.get(&cache_key) {
+            return send_cached(&entry, options, req, res);
+        }
+    }
+
+    let path = match resolve_path(root, rest, options.follow_symlinks) {
+        Some(path) if path.is_file() => path,
+        _ => {
+            return res.send_code(ResponseCode::NotFound);
+        }
+    };
+
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(_err) => {
+            return res.send_code(ResponseCode::NotFound);
+        }
+    };
+    let metadata = file.metadata().map_err(|e| HttpServerError::new(&e.to_string()))?;
+    let size = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = format!(
+        "\"{:x}-{:x}\"",
+        size,
+        modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default()
+    );
+
+    let mut bfreader = BufReader::new(file);
+
+    let mut content_type = options.content_type(&path);
+    if content_type.is_none() && options.sniff_content_type {
+        // Peek without consuming, the buffer is still sent afterwards
+        let head = bfreader.fill_buf().map_err(|e| HttpServerError::new(&e.to_string()))?;
+        content_type = ContentType::sniff(&head[..head.len().min(512)]);
+    }
+    if is_gzipped(&path) {
+        res.set_header(&Header::new("Content-Encoding", &EncodingType::Gzip.to_string()));
+    }
+
+    if let Some(cache) = &options.cache {
+        if cache.accepts(size) {
+            let mut buff: Vec<u8> = Vec::new();
+            bfreader.read_to_end(&mut buff).map_err(|e| HttpServerError::new(&e.to_string()))?;
+
+            let file = CachedFile::new(path, buff, content_type, etag, modified);
+            let entry = cache.insert(&cache_key, file);
+            return send_cached(&entry, options, req, res);
+        }
+    }
+
+    res.set_header(&Header::new("ETag", &etag));
+    if is_not_modified(req, &etag) {
+        return res.send_code(ResponseCode::NotModified);
+    }
+    res.set_header(&Header::new("Accept-Ranges", "bytes"));
+
+    match requested_range(req, &etag, size) {
+        Ok(Some((start, end))) => {
+            res.set_status(&ResponseCode::PartialContent);
+            res.set_header(
+                &Header::new("Content-Range", &format!("bytes {}-{}/{}", start, end, size))
+            );
+            return res.send_file(bfreader.get_ref(), start, end - start + 1, content_type);
+        }
+        Ok(None) => {}
+        Err(()) => {
+            return send_unsatisfiable(res, size);
+        }
+    }
+
+    if is_gzipped(&path) || !should_compress(options, content_type.as_ref()) {
+        return res.send_file(bfreader.get_ref(), 0, size, content_type);
+    }
+    res.append_header(&Header::new("Vary", "Accept-Encoding"));
+    if !res.accepts_gzip() {
+        return res.send_file(bfreader.get_ref(), 0, size, content_type);
+    }
+    // Compressed size isn't known before compressing the whole file
+    let mut buff: Vec<u8> = Vec::new();
+    bfreader.read_to_end(&mut buff).map_err(|e| HttpServerError::new(&e.to_string()))?;
+    res.set_header(&Header::new("Content-Encoding", &EncodingType::Gzip.to_string()));
+    if route.chunked {
+        res.send_bytes_chunked(&gzip(&buff), content_type)
+    } else {
+        res.send_bytes(&gzip(&buff), content_type)
+    }
+}
+
+fn send_cached(
+    entry: &CachedFile,
+    options: &StaticOptions,
+    req: &Request,
+    res: &mut Response
+) -> Result<(), HttpServerError> {
+    let content_type = entry.content_type.clone();
+    let compress = !is_gzipped(&entry.path) && should_compress(options, content_type.as_ref());
+    if is_gzipped(&entry.path) {
+        res.set_header(&Header::new("Content-Encoding", &EncodingType::Gzip.to_string()));
+    }
+    send_static_bytes(&entry.data, || entry.gzip(), content_type, &entry.etag, compress, req, res)
+}
+
+/// Sends in-memory static data with the same conditional, range and compression handling as files.
+/// `compress` is `should_compress` for the data, `gzip` gives the compressed data and is only
+/// called if the client accepts it.
+pub fn send_static_bytes<'a>(
+    data: &[u8],
+    gzip: impl FnOnce() -> &'a [u8],
+    content_type: Option<ContentType>,
+    etag: &str,
+    compress: bool,
+    req: &Request,
+    res: &mut Response
+) -> Result<(), HttpServerError> {
+    res.set_header(&Header::new("ETag", etag));
+    if is_not_modified(req, etag) {
+        return res.send_code(ResponseCode::NotModified);
+    }
+    res.set_header(&Header::new("Accept-Ranges", "bytes"));
+
+    let size = data.len() as u64;
+    match requested_range(req, etag, size) {
+        Ok(Some((start, end))) => {
+            res.set_status(&ResponseCode::PartialContent);
+            res.set_header(
+                &Header::new("Content-Range", &format!("bytes {}-{}/{}", start, end, size))
+            );
+            return res.send_bytes(&data[start as usize..=end as usize], content_type);
+        }
+        Ok(None) => {}
+        Err(()) => {
+            return send_unsatisfiable(res, size);
+        }
+    }
+
+    if compress {
+        res.append_header(&Header::new("Vary", "Accept-Encoding"));
+        if res.accepts_gzip() {
+            res.set_header(&Header::new("Content-Encoding", &EncodingType::Gzip.to_string()));
+            return res.send_bytes(gzip(), content_type);
+        }
+    }
+    res.send_bytes(data, content_type)
+}
+
+fn send_unsatisfiable(res: &mut Response, size: u64) -> Result<(), HttpServerError> {
+    res.set_header(&Header::new("Content-Range", &format!("bytes */{}", size)));
+    res.send_code(ResponseCode::RangeNotSatisfiable)
+}
+
+/// True if the client already has this version (If-None-Match).
+fn is_not_modified(req: &Request, etag: &str) -> bool {
+    match req.headers.get("If-None-Match") {
+        Some(value) => {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        }
+        None => false,
+    }
+}
+
+/// The byte range (inclusive) asked with the Range header.
+/// Ok(None) means the whole body should be sent and Err that the range can't be satisfied.
+/// Only single ranges are supported, multiple ranges get the whole body.
+fn requested_range(req: &Request, etag: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let range = match req.headers.get("Range") {
+        Some(range) => range,
+        None => {
+            return Ok(None);
+        }
+    };
+    if let Some(if_range) = req.headers.get("If-Range") {
+        if if_range.trim() != etag {
+            return Ok(None);
+        }
+    }
+    parse_range(range, size)
+}
+
+/// Parses a "bytes=start-end" range header against a body of `size` bytes.
+pub fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) => spec.trim(),
+        None => {
+            return Ok(None);
+        }
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let (start, end) = if start.is_empty() {
+        // Suffix range: the last n bytes
+        let suffix: u64 = end.parse().map_err(|_| ())?;
+        if suffix == 0 || size == 0 {
+            return Err(());
+        }
+        (size.saturating_sub(suffix), size - 1)
+    } else {
+        let start: u64 = start.parse().map_err(|_| ())?;
+        let end: u64 = if end.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            end.parse().map_err(|_| ())?
+        };
+        (start, end.min(size.saturating_sub(1)))
+    };
+    if start >= size || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+fn should_compress(options: &StaticOptions, content_type: Option<&ContentType>) -> bool {
+    if !options.compress {
+        return false;
+    }
+    let mime = match content_type {
+        Some(content_type) => content_type.as_str(),
+        None => {
+            return false;
+        }
+    };
+    mime.starts_with("text/") ||
+        mime.ends_with("+xml") ||
+        mime.ends_with("+json") ||
+        matches!(mime, "application/json" | "application/wasm" | "application/xml" | "image/x-icon")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ is_gzipped, resolve_path };
+    use std::path::{ Path, PathBuf };
+
+    fn resolve(rest: &str) -> Option<PathBuf> {
+        let root = Path::new("tests/static").canonicalize().unwrap();
+        resolve_path(&root, rest, false)
+    }
+
+    #[test]
+    fn resolves_files_under_the_root() {
+        let root = Path::new("tests/static").canonicalize().unwrap();
+        assert_eq!(resolve("/index.html"), Some(root.join("index.html")));
+        assert_eq!(resolve("//test/./image.gif"), Some(root.join("test").join("image.gif")));
+        assert_eq!(resolve("/"), None);
+        assert_eq!(resolve("/missing.html"), None);
+    }
+
+    #[test]
+    fn svgz_is_already_gzipped() {
+        assert!(is_gzipped(Path::new("icons/logo.SVGZ")));
+        assert!(!is_gzipped(Path::new("icons/logo.svg")));
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        assert_eq!(resolve("/../Cargo.toml"), None);
+        assert_eq!(resolve("/test/../../Cargo.toml"), None);
+        assert_eq!(resolve("/test/../index.html"), None);
+    }
+
+    #[test]
+    fn rejects_nul_and_backslash() {
+        assert_eq!(resolve("/index.html\0"), None);
+        assert_eq!(resolve("/test\\image.gif"), None);
+        assert_eq!(resolve("/..\\Cargo.toml"), None);
+    }
+}
@@ -1,11 +1,32 @@
-use std::{ collections::HashMap, io::BufRead };
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{ Duration, SystemTime },
+};
 
 use urlencoding::decode;
 
 use super::{
     request::Request,
     response::Response,
-    utils::utils::{ contains_blank, count_char_occurrences },
+    static_files::EmbeddedAssets,
+    multipart::TempFile,
+    parser::{
+        decode_ext_value,
+        is_token,
+        parse_header_line,
+        parse_header_params,
+        parse_query,
+        parse_request_line,
+    },
+    utils::{
+        http_date::format_http_date,
+        mime::{ mime_from_extension, sniff_mime },
+        utils::{ contains_blank, count_char_occurrences, normalize_segments },
+    },
 };
 
 #[derive(Clone, PartialEq)]
@@ -38,6 +59,18 @@ impl RequestType {
         };
         Ok(res)
     }
+    pub fn as_str(&self) -> &str {
+        match self {
+            RequestType::Get => "GET",
+            RequestType::Post => "POST",
+            RequestType::Put => "PUT",
+            RequestType::Delete => "DELETE",
+            RequestType::Head => "HEAD",
+            RequestType::Options => "OPTIONS",
+            RequestType::Patch => "PATCH",
+            RequestType::Other(method) => method,
+        }
+    }
 }
 #[derive(Clone, PartialEq)]
 pub enum EncodingType {
@@ -167,48 +200,91 @@ impl ContentType {
         };
         Ok(res)
     }
-    pub fn from_extension(extension: &str) -> Option<ContentType> {
-        match extension.to_lowercase().as_str() {
-            // Text Types
-            "txt" => Some(ContentType::PlainText),
-            "html" => Some(ContentType::Html),
-            "css" => Some(ContentType::Css),
-            "js" => Some(ContentType::Javascript),
-            "ts" => Some(ContentType::Javascript),
-            "xml" => Some(ContentType::Xml),
-            "xaml" => Some(ContentType::Xml),
-
-            // Application Types
-            "json" => Some(ContentType::Json),
-
-            // Image Types
-            "jpeg" => Some(ContentType::Jpeg),
-            "png" => Some(ContentType::Png),
-            "gif" => Some(ContentType::Gif),
-            "webp" => Some(ContentType::Webp),
-            "svg" => Some(ContentType::SvgXml),
-            // Video Types
-            "mkv" => Some(ContentType::Mkv),
-            "mp4" => Some(ContentType::Mp4),
-
-            _ => None,
+    /// True for application/json and the +json types (application/problem+json...)
+    pub fn is_json(&self) -> bool {
+        match self {
+            ContentType::Json => true,
+            ContentType::Other(mime) => mime.ends_with("+json"),
+            _ => false,
         }
     }
+    /// Value for the Content-Type header. Text types without parameters get a utf-8 charset.
+    pub fn header_value(&self) -> String {
+        let mime = self.as_str();
+        if mime.starts_with("text/") && !mime.contains(';') {
+            return format!("{}; charset=utf-8", mime);
+        }
+        mime.to_owned()
+    }
+    pub fn from_extension(extension: &str) -> Option<ContentType> {
+        let mime = mime_from_extension(extension)?;
+        ContentType::from_string(mime).ok()
+    }
+    /// Guesses the content type from the first bytes of the data (magic bytes or plain text).
+    pub fn sniff(data: &[u8]) -> Option<ContentType> {
+        let mime = sniff_mime(data)?;
+        ContentType::from_string(mime).ok()
+    }
 }
 
 #[derive(Debug)]
 pub struct HttpServerError {
     pub reason: String,
+    /// Response code that should be sent to the client because of this error
+    pub code: Option<ResponseCode>,
 }
 impl HttpServerError {
     pub fn new(reason: &str) -> HttpServerError {
-        return HttpServerError { reason: reason.to_string() };
+        return HttpServerError { reason: reason.to_string(), code: None };
+    }
+    pub fn with_code(code: ResponseCode, reason: &str) -> HttpServerError {
+        return HttpServerError { reason: reason.to_string(), code: Some(code) };
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+    Other(u8, u8),
+}
+impl HttpVersion {
+    /// Parses "HTTP/x.y"
+    pub fn from_string(input: &str) -> Result<HttpVersion, HttpServerError> {
+        let version = match input.strip_prefix("HTTP/") {
+            Some(version) => version.as_bytes(),
+            None => {
+                return Err(HttpServerError::with_code(ResponseCode::BadRequest, "Invalid version!"));
+            }
+        };
+        if version.len() != 3 || version[1] != b'.' {
+            return Err(HttpServerError::with_code(ResponseCode::BadRequest, "Invalid version!"));
+        }
+        let (major, minor) = (version[0], version[2]);
+        if !major.is_ascii_digit() || !minor.is_ascii_digit() {
+            return Err(HttpServerError::with_code(ResponseCode::BadRequest, "Invalid version!"));
+        }
+        let res = match (major - b'0', minor - b'0') {
+            (1, 0) => HttpVersion::Http10,
+            (1, 1) => HttpVersion::Http11,
+            (major, minor) => HttpVersion::Other(major, minor),
+        };
+        Ok(res)
+    }
+    pub fn to_string(&self) -> String {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0".to_owned(),
+            HttpVersion::Http11 => "HTTP/1.1".to_owned(),
+            HttpVersion::Other(major, minor) => format!("HTTP/{}.{}", major, minor),
+        }
     }
 }
 pub struct Url {
     pub path: String,
     pub req_type: RequestType,
     pub query: HashMap<String, String>,
+    /// The raw query (after '?'), not decoded
+    pub query_string: String,
+    pub version: HttpVersion,
 }
 impl Url {
     pub fn new(path: String, req_type: RequestType, query: HashMap<String, String>) -> Url {
@@ -216,49 +292,56 @@ impl Url {
             path: path,
             req_type: req_type,
             query: query,
+            query_string: String::new(),
+            version: HttpVersion::Http11,
         };
     }
+    /// Parses the request line ("GET /path?query HTTP/1.1")
     pub fn parse(input: &str) -> Result<Url, HttpServerError> {
-        let parts: Vec<&str> = input.split(" ").collect();
-        if parts.len() != 3 {
-            return Err(HttpServerError::new("Invalid input."));
-        }
-        let req_type: RequestType = RequestType::from_string(&parts[0].to_lowercase())?;
-
-        let mut path: &str = &decode(parts[1]).unwrap_or_default().to_string();
-        //Clean url
-        let clean_path = path.replace("..", "").replace("//", "/");
-        path = &clean_path;
-        //
-        let mut query: HashMap<String, String> = HashMap::new();
-
-        if path.contains("?") == true {
-            let parts: (&str, &str) = path.split_once("?").unwrap();
-
-            path = parts.0;
-
-            if parts.1.len() > 2 && parts.1.contains("=") == true {
-                if parts.1.contains("&") {
-                    let queries_string: Vec<&str> = parts.1.split("&").collect();
-                    for query_string in queries_string {
-                        let query_string: Option<(&str, &str)> = query_string.split_once("=");
-                        if query_string.is_some() {
-                            let query_string = query_string.unwrap();
-                            query.insert(query_string.0.to_string(), query_string.1.to_string());
-                        }
-                    }
-                } else {
-                    let query_string: Option<(&str, &str)> = parts.1.split_once("=");
+        let (method, target, version) = parse_request_line(input)?;
+
+        let req_type: RequestType = RequestType::from_string(method)?;
+        let version = HttpVersion::from_string(version)?;
+
+        // Absolute form (http://host/path) is turned into the path
+        let mut target = target;
+        if let Some(scheme_end) = target.find("://") {
+            let after_scheme = &target[scheme_end + 3..];
+            target = match after_scheme.find(['/', '?']) {
+                Some(index) => &after_scheme[index..],
+                None => "/",
+            };
+        }
+        if !target.starts_with('/') && !(target == "*" && req_type == RequestType::Options) {
+            return Err(HttpServerError::with_code(ResponseCode::BadRequest, "Invalid target!"));
+        }
 
-                    if query_string.is_some() {
-                        let query_string = query_string.unwrap();
-                        query.insert(query_string.0.to_string(), query_string.1.to_string());
-                    }
-                }
+        // The query is split before decoding so encoded '&' and '=' stay in the values
+        let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+
+        let path = decode(path).map_err(|_|
+            HttpServerError::with_code(ResponseCode::BadRequest, "Invalid path encoding!")
+        )?;
+        // Clean url, every later step (routes, static files) uses this path
+        let path = if path == "*" {
+            path.to_string()
+        } else {
+            let segments = normalize_segments(&path).ok_or_else(||
+                HttpServerError::with_code(ResponseCode::BadRequest, "Invalid path!")
+            )?;
+            let mut clean = format!("/{}", segments.join("/"));
+            if path.ends_with('/') && !segments.is_empty() {
+                clean.push('/');
             }
-        }
+            clean
+        };
+
+        let query: HashMap<String, String> = parse_query(query_string).into_iter().collect();
 
-        Ok(Url::new(path.to_owned(), req_type, query))
+        let mut url = Url::new(path, req_type, query);
+        url.query_string = query_string.to_owned();
+        url.version = version;
+        Ok(url)
     }
     pub fn match_patern(input: &str, pattern: &str) -> (bool, HashMap<String, String>) {
         let parts_input: Vec<&str> = input
@@ -296,12 +379,18 @@ impl Url {
         return (true, params);
     }
 }
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Cookie {
     pub name: String,
     pub value: String,
     pub path: String,
     pub expires: String,
+    pub domain: String,
+    /// Seconds until it expires (0 or less deletes it)
+    pub max_age: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
 }
 impl Cookie {
     /// Creates a simple cookie with name and value
@@ -311,8 +400,60 @@ impl Cookie {
             value: value,
             path: "".to_string(),
             expires: "".to_string(),
+            domain: "".to_string(),
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
         };
     }
+    /// Creates a cookie to add attributes to with the `with_*` methods
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie::new_simple(name.to_owned(), value.to_owned())
+    }
+    pub fn with_path(mut self, path: &str) -> Cookie {
+        self.path = path.to_owned();
+        self
+    }
+    pub fn with_domain(mut self, domain: &str) -> Cookie {
+        self.domain = domain.to_owned();
+        self
+    }
+    /// Expires at a time
+    pub fn with_expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = format_http_date(expires);
+        self
+    }
+    /// Expires after a duration (Max-Age)
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age.as_secs() as i64);
+        self
+    }
+    /// Only sent over https
+    pub fn with_secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+    /// Not readable from javascript
+    pub fn with_http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+    /// SameSite=None cookies are always sent with Secure (browsers reject them otherwise)
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+    /// True if it can be sent without breaking the header: the name is a token and
+    /// the value and attributes have no control characters or ';'
+    pub fn is_valid(&self) -> bool {
+        let is_safe = |input: &str| !input.chars().any(|char| char.is_control() || char == ';');
+        is_token(&self.name) &&
+            is_safe(&self.value) &&
+            is_safe(&self.path) &&
+            is_safe(&self.expires) &&
+            is_safe(&self.domain)
+    }
     /// Converts cookie into str for writing in response
     pub fn as_str(&self) -> String {
         let mut cookie_str = format!("{}={}", self.name, self.value);
@@ -320,23 +461,73 @@ impl Cookie {
         if !self.path.is_empty() {
             cookie_str.push_str(&format!("; Path={}", self.path));
         }
-
+        if !self.domain.is_empty() {
+            cookie_str.push_str(&format!("; Domain={}", self.domain));
+        }
         if !self.expires.is_empty() {
             cookie_str.push_str(&format!("; Expires={}", self.expires));
         }
+        if let Some(max_age) = self.max_age {
+            cookie_str.push_str(&format!("; Max-Age={}", max_age));
+        }
+        if self.secure || self.same_site == Some(SameSite::None) {
+            cookie_str.push_str("; Secure");
+        }
+        if self.http_only {
+            cookie_str.push_str("; HttpOnly");
+        }
+        if let Some(same_site) = &self.same_site {
+            cookie_str.push_str(&format!("; SameSite={}", same_site.as_str()));
+        }
 
         cookie_str
     }
-    /// generates set-cookie headers
+    /// generates set-cookie headers ("Set-Cookie: {cookie}\r\n" for each).
+    /// Invalid cookies are skipped.
     pub fn generate_set_cookie_headers(cookies: &Vec<Cookie>) -> String {
         cookies
             .iter()
-            .map(|cookie| format!("\r\nSet-Cookie: {}", cookie.as_str()))
+            .filter(|cookie| cookie.is_valid())
+            .map(|cookie| format!("Set-Cookie: {}\r\n", cookie.as_str()))
             .collect()
     }
+    /// Parses a Cookie header ("a=1; b=2"). Values can contain '=' and quotes around them are removed.
+    pub fn parse_header(input: &str) -> Vec<Cookie> {
+        input
+            .split(';')
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some(Cookie::new(name, value))
+            })
+            .collect()
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Header {
     pub name: String,
     pub value: String,
@@ -353,20 +544,117 @@ impl Header {
     pub fn as_str(&self) -> String {
         format!("{}: {}", self.name, self.value)
     }
-    /// Generates headers into string from a list/vec
+    /// Generates headers into string from a list/vec ("{name}: {value}\r\n" for each)
     pub fn generate_headers(headers: &Vec<Header>) -> String {
-        let mut headers_str = "\n".to_string();
-        if headers.len() == 0 {
-            return "".to_string();
-        }
-        let last_header = headers.last().unwrap().name.clone();
-        for header in headers {
-            headers_str.push_str(&header.as_str());
-            if last_header != header.name {
-                headers_str.push_str("\r\n");
+        headers
+            .iter()
+            .map(|header| format!("{}\r\n", header.as_str()))
+            .collect()
+    }
+}
+
+/// Headers with case insensitive names. Keeps the order they were added in and
+/// supports multiple values for the same name.
+#[derive(Clone, Default, Debug)]
+pub struct HeaderMap {
+    headers: Vec<Header>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        return HeaderMap { headers: Vec::new() };
+    }
+    /// First value of the header
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+    /// Every value of the header
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+            .collect()
+    }
+    /// First value of the header parsed into `T` (None if missing or invalid)
+    pub fn get_parsed<F: FromStr>(&self, name: &str) -> Option<F> {
+        self.get(name)?.trim().parse::<F>().ok()
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    /// Sets the header, replacing every value it had
+    pub fn insert(&mut self, name: &str, value: &str) {
+        match self.headers.iter().position(|header| header.name.eq_ignore_ascii_case(name)) {
+            Some(index) => {
+                self.headers[index] = Header::new(name, value);
+                let mut i = index + 1;
+                while i < self.headers.len() {
+                    if self.headers[i].name.eq_ignore_ascii_case(name) {
+                        self.headers.remove(i);
+                    } else {
+                        i += 1;
+                    }
+                }
             }
+            None => self.headers.push(Header::new(name, value)),
         }
-        headers_str
+    }
+    /// Adds a value without removing the existing ones
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.headers.push(Header::new(name, value));
+    }
+    /// Removes every value of the header. Returns true if there was any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.headers.len();
+        self.headers.retain(|header| !header.name.eq_ignore_ascii_case(name));
+        len != self.headers.len()
+    }
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Header> {
+        self.headers.iter()
+    }
+    // Typed accessors
+    pub fn content_length(&self) -> Option<usize> {
+        self.get_parsed::<usize>("Content-Length")
+    }
+    /// Content type without its parameters
+    pub fn content_type(&self) -> Option<ContentType> {
+        let value = self.get("Content-Type")?;
+        ContentType::from_string(value.split(';').next().unwrap_or_default().trim()).ok()
+    }
+    pub fn user_agent(&self) -> Option<&str> {
+        self.get("User-Agent")
+    }
+    pub fn host(&self) -> Option<&str> {
+        self.get("Host")
+    }
+    /// Generates the headers into string ("{name}: {value}\r\n" for each)
+    pub fn as_str(&self) -> String {
+        Header::generate_headers(&self.headers)
+    }
+}
+
+impl From<Vec<Header>> for HeaderMap {
+    fn from(headers: Vec<Header>) -> HeaderMap {
+        return HeaderMap { headers: headers };
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = &'a Header;
+    type IntoIter = std::slice::Iter<'a, Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.headers.iter()
     }
 }
 
@@ -401,6 +689,44 @@ impl<T: Clone + std::marker::Send + 'static> EndPoint<T> {
         };
     }
 }
+/// Where the files of a static endpoint come from.
+#[derive(Clone)]
+pub enum StaticSource {
+    /// Canonical path of a folder on disk
+    Folder(PathBuf),
+    /// Files compiled into the binary
+    Embedded(Arc<EmbeddedAssets>),
+}
+
+/// Files exposed under a url path.
+#[derive(Clone)]
+pub struct StaticEndpoint {
+    pub path: String,
+    pub source: StaticSource,
+    pub chunked: bool,
+}
+
+impl StaticEndpoint {
+    pub fn new(path: String, source: StaticSource, chunked: bool) -> StaticEndpoint {
+        return StaticEndpoint {
+            path: path,
+            source: source,
+            chunked: chunked,
+        };
+    }
+    /// Returns the part of the url after the mount path or None if the url is not under it.
+    /// "/static" matches "/static" and "/static/a.png" but not "/staticfoo".
+    pub fn strip_mount<'a>(&self, url_path: &'a str) -> Option<&'a str> {
+        if self.path == "/" {
+            return Some(url_path);
+        }
+        let rest = url_path.strip_prefix(&self.path)?;
+        if rest.is_empty() || rest.starts_with('/') {
+            return Some(rest);
+        }
+        None
+    }
+}
 /// Info for bodyitem: name,content type, file name if its a file, and value if urlencoded
 #[derive(Clone)]
 pub struct BodyItemInfo {
@@ -408,6 +734,10 @@ pub struct BodyItemInfo {
     pub name: Option<String>,
 
     pub file_name: Option<String>, // Special only for files
+    /// Every header of a multipart part
+    pub headers: HeaderMap,
+    /// The data of a file part written to disk (see `spool_threshold`), `data` is empty then
+    pub temp_file: Option<Arc<TempFile>>,
 
     value: Option<String>, // Only for urlencoded
 }
@@ -418,6 +748,8 @@ impl BodyItemInfo {
             content_type: ContentType::None,
             name: None,
             file_name: None,
+            headers: HeaderMap::new(),
+            temp_file: None,
             value: None,
         };
     }
@@ -426,6 +758,8 @@ impl BodyItemInfo {
             content_type: content_type,
             name: None,
             file_name: None,
+            headers: HeaderMap::new(),
+            temp_file: None,
             value: None,
         };
     }
@@ -434,53 +768,60 @@ impl BodyItemInfo {
             content_type: ContentType::UrlEncoded,
             name: Some(name),
             file_name: None,
+            headers: HeaderMap::new(),
+            temp_file: None,
             value: Some(value),
         };
     }
     pub fn to_body_item(&self) -> BodyItem {
         return BodyItem::new_url(self, self.value.clone().unwrap_or_default());
     }
+    /// Parses the header lines of a multipart part
     pub fn from_str(input: &str) -> Result<BodyItemInfo, HttpServerError> {
-        let lines: Vec<&str> = input.lines().collect();
-
-        let mut body_item = BodyItemInfo::default();
-
-        if lines.len() == 0 {
-            return Ok(body_item);
+        let mut headers = HeaderMap::new();
+        for line in input.lines().filter(|line| !line.is_empty()) {
+            let (name, value) = parse_header_line(line)?;
+            headers.append(name, value);
         }
+        BodyItemInfo::from_headers(headers)
+    }
+    /// Info of a multipart part from its headers (RFC 7578).
+    /// Parts without a Content-Type are text/plain and `filename*` wins over `filename`.
+    pub fn from_headers(headers: HeaderMap) -> Result<BodyItemInfo, HttpServerError> {
+        let mut body_item = BodyItemInfo::default();
 
-        let mut parts: Vec<&str> = Vec::new();
-
-        if lines.len() > 1 {
-            parts = lines[1].split(": ").collect();
-            if parts.len() > 1 {
-                body_item.content_type = ContentType::from_string(parts[1])?;
-            } else {
-                return Ok(BodyItemInfo::default());
+        body_item.content_type = match headers.get("Content-Type") {
+            Some(value) => {
+                let (media_type, _) = parse_header_params(value);
+                ContentType::from_string(&media_type).map_err(|e|
+                    HttpServerError::with_code(ResponseCode::BadRequest, &e.reason)
+                )?
             }
-        } else {
-            body_item.content_type = ContentType::MultipartForm;
-        }
-
-        parts = lines[0].split("; ").collect();
+            None => ContentType::PlainText,
+        };
 
-        if parts.len() < 2 || (parts.len() != 3 && lines.len() == 2) {
-            return Ok(BodyItemInfo::default());
-        }
-        if lines.len() == 2 {
-            let file_name = parts[2].to_string();
-            if file_name.len() > 11 {
-                body_item.file_name = Some(
-                    parts[2].to_string()[10..file_name.len() - 1].to_string()
-                );
+        if let Some(value) = headers.get("Content-Disposition") {
+            let (_, params) = parse_header_params(value);
+            let mut ext_file_name: Option<String> = None;
+            for (name, value) in params {
+                match name.as_str() {
+                    "name" => {
+                        body_item.name = Some(value);
+                    }
+                    "filename" => {
+                        body_item.file_name = Some(value);
+                    }
+                    "filename*" => {
+                        ext_file_name = decode_ext_value(&value);
+                    }
+                    _ => {}
+                }
+            }
+            if ext_file_name.is_some() {
+                body_item.file_name = ext_file_name;
             }
         }
-        let name = parts[1].replace("name=\"", "");
-        if name.len() < 1 {
-            body_item.name = None;
-        } else {
-            body_item.name = Some(name[0..name.len() - 1].to_string());
-        }
+        body_item.headers = headers;
 
         return Ok(body_item);
     }
@@ -511,12 +852,25 @@ impl<'a> BodyItem<'a> {
 pub enum ResponseCode {
     Continue,
     Ok,
+    Created,
+    NoContent,
     PartialContent,
+    NotModified,
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
     MethodNotAllowed,
+    RequestTimeout,
     ContentTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
     RangeNotSatisfiable,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    InternalServerError,
+    ServiceUnavailable,
+    HttpVersionNotSupported,
 
     Other(i64),
 }
@@ -526,12 +880,25 @@ impl ResponseCode {
         match self {
             ResponseCode::Continue => 100,
             ResponseCode::Ok => 200,
+            ResponseCode::Created => 201,
+            ResponseCode::NoContent => 204,
             ResponseCode::PartialContent => 206,
+            ResponseCode::NotModified => 304,
             ResponseCode::BadRequest => 400,
+            ResponseCode::Unauthorized => 401,
+            ResponseCode::Forbidden => 403,
             ResponseCode::NotFound => 404,
             ResponseCode::MethodNotAllowed => 405,
+            ResponseCode::RequestTimeout => 408,
             ResponseCode::ContentTooLarge => 413,
+            ResponseCode::UriTooLong => 414,
+            ResponseCode::UnsupportedMediaType => 415,
             ResponseCode::RangeNotSatisfiable => 416,
+            ResponseCode::TooManyRequests => 429,
+            ResponseCode::RequestHeaderFieldsTooLarge => 431,
+            ResponseCode::InternalServerError => 500,
+            ResponseCode::ServiceUnavailable => 503,
+            ResponseCode::HttpVersionNotSupported => 505,
             ResponseCode::Other(code) => *code as u16,
         }
     }
@@ -540,12 +907,25 @@ impl ResponseCode {
         match code {
             100 => ResponseCode::Continue,
             200 => ResponseCode::Ok,
+            201 => ResponseCode::Created,
+            204 => ResponseCode::NoContent,
             206 => ResponseCode::PartialContent,
+            304 => ResponseCode::NotModified,
             400 => ResponseCode::BadRequest,
+            401 => ResponseCode::Unauthorized,
+            403 => ResponseCode::Forbidden,
             404 => ResponseCode::NotFound,
             405 => ResponseCode::MethodNotAllowed,
+            408 => ResponseCode::RequestTimeout,
             413 => ResponseCode::ContentTooLarge,
+            414 => ResponseCode::UriTooLong,
+            415 => ResponseCode::UnsupportedMediaType,
             416 => ResponseCode::RangeNotSatisfiable,
+            429 => ResponseCode::TooManyRequests,
+            431 => ResponseCode::RequestHeaderFieldsTooLarge,
+            500 => ResponseCode::InternalServerError,
+            503 => ResponseCode::ServiceUnavailable,
+            505 => ResponseCode::HttpVersionNotSupported,
 
             _ => ResponseCode::Other(code),
         }
@@ -555,12 +935,25 @@ impl ResponseCode {
         match self.as_u16() {
             100 => "Continue".to_owned(),
             200 => "OK".to_owned(),
+            201 => "Created".to_owned(),
+            204 => "No Content".to_owned(),
             206 => "Partial Content".to_owned(),
+            304 => "Not Modified".to_owned(),
             400 => "Bad Request".to_owned(),
+            401 => "Unauthorized".to_owned(),
+            403 => "Forbidden".to_owned(),
             404 => "NOT FOUND".to_owned(),
             405 => "Method Not Allowed".to_owned(),
+            408 => "Request Timeout".to_owned(),
             413 => "Content Too Large".to_owned(),
+            414 => "URI Too Long".to_owned(),
+            415 => "Unsupported Media Type".to_owned(),
             416 => "Range Not Satisfiable".to_owned(),
+            429 => "Too Many Requests".to_owned(),
+            431 => "Request Header Fields Too Large".to_owned(),
+            500 => "Internal Server Error".to_owned(),
+            503 => "Service Unavailable".to_owned(),
+            505 => "HTTP Version Not Supported".to_owned(),
 
             _ => "Unknown".to_owned(),
         }
@@ -574,3 +967,43 @@ impl ResponseCode {
         format!("{} {}", &self.to_string(), &self.to_desc())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ ContentType, ResponseCode, Url };
+
+    fn parse_path(target: &str) -> Result<String, Option<ResponseCode>> {
+        Url::parse(&format!("GET {} HTTP/1.1", target))
+            .map(|url| url.path)
+            .map_err(|e| e.code)
+    }
+
+    #[test]
+    fn url_path_is_normalized() {
+        assert_eq!(parse_path("/files/./private/secret.txt"), Ok("/files/private/secret.txt".to_owned()));
+        assert_eq!(parse_path("/files/.%2Fprivate/secret.txt"), Ok("/files/private/secret.txt".to_owned()));
+        assert_eq!(parse_path("//files//a/"), Ok("/files/a/".to_owned()));
+        assert_eq!(parse_path("/"), Ok("/".to_owned()));
+        assert_eq!(parse_path("/a%20b?x=1"), Ok("/a b".to_owned()));
+    }
+
+    #[test]
+    fn url_rejects_bad_paths() {
+        let bad_request = Err(Some(ResponseCode::BadRequest));
+        assert_eq!(parse_path("/files/%5Cprivate/secret.txt"), bad_request);
+        assert_eq!(parse_path("/files/../secret.txt"), bad_request);
+        assert_eq!(parse_path("/files/%2E%2E/secret.txt"), bad_request);
+        assert_eq!(parse_path("/files/a%00b"), bad_request);
+        assert_eq!(parse_path("/files/%FF"), bad_request);
+    }
+
+    #[test]
+    fn charset_is_added_once() {
+        assert_eq!(ContentType::PlainText.header_value(), "text/plain; charset=utf-8");
+        assert_eq!(ContentType::Json.header_value(), "application/json");
+        let latin = ContentType::Other("text/plain; charset=iso-8859-1".to_owned());
+        assert_eq!(latin.header_value(), "text/plain; charset=iso-8859-1");
+        let csv = ContentType::from_string("text/csv;charset=latin1").unwrap();
+        assert_eq!(csv.header_value(), "text/csv;charset=latin1");
+    }
+}
@@ -0,0 +1,64 @@
+use std::{ io::{ self, BufRead, BufReader, Read }, net::TcpStream, time::{ Duration, Instant } };
+
+/// Reader that fails with `TimedOut` once the deadline has passed, no matter how slowly the
+/// client keeps sending data. The socket read timeout is set to the remaining time before every
+/// read from the socket.
+pub struct DeadlineReader<'a> {
+    reader: &'a mut BufReader<TcpStream>,
+    deadline: Option<Instant>,
+}
+
+impl<'a> DeadlineReader<'a> {
+    /// No deadline if `timeout` is None
+    pub fn new(reader: &'a mut BufReader<TcpStream>, timeout: Option<Duration>) -> DeadlineReader<'a> {
+        return DeadlineReader {
+            reader: reader,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        };
+    }
+    fn prepare(&mut self) -> io::Result<()> {
+        // Data already buffered doesn't touch the socket
+        if !self.reader.buffer().is_empty() {
+            return Ok(());
+        }
+        set_deadline(self.reader.get_ref(), self.deadline)
+    }
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.prepare()?;
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for DeadlineReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.prepare()?;
+        self.reader.fill_buf()
+    }
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount);
+    }
+}
+
+/// Sets the read timeout of the stream to the time left until the deadline.
+/// Fails with `TimedOut` if it has already passed.
+pub fn set_deadline(stream: &TcpStream, deadline: Option<Instant>) -> io::Result<()> {
+    let deadline = match deadline {
+        Some(deadline) => deadline,
+        None => {
+            return stream.set_read_timeout(None);
+        }
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "Deadline passed"));
+    }
+    stream.set_read_timeout(Some(remaining))
+}
+
+/// True if the error means a socket timeout
+pub fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
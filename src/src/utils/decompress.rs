@@ -0,0 +1,116 @@
+use std::{ error::Error, fmt, io::{ self, BufRead, BufReader, Read } };
+
+use flate2::bufread::{ DeflateDecoder, GzDecoder, ZlibDecoder };
+
+use crate::src::structs::{ HttpServerError, ResponseCode };
+
+use super::deadline::is_timeout;
+
+/// Wraps the body reader so the body is decoded (Content-Encoding: gzip, deflate or identity).
+/// Codings are undone in reverse order and the decoded body can't be longer than `max_length`
+/// (0 for no limit).
+pub fn decoding_reader<'a>(
+    reader: Box<dyn Read + 'a>,
+    content_encoding: &str,
+    max_length: usize
+) -> Result<Box<dyn Read + 'a>, HttpServerError> {
+    let mut reader = reader;
+    let mut decoded = false;
+
+    for coding in content_encoding.rsplit(',') {
+        reader = match coding.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => {
+                continue;
+            }
+            "gzip" | "x-gzip" => Box::new(GzDecoder::new(BufReader::new(reader))),
+            "deflate" => deflate_reader(BufReader::new(reader))?,
+            coding => {
+                return Err(
+                    HttpServerError::with_code(
+                        ResponseCode::UnsupportedMediaType,
+                        &format!("Unsupported Content-Encoding {}!", coding)
+                    )
+                );
+            }
+        };
+        decoded = true;
+    }
+    if decoded && max_length > 0 {
+        return Ok(
+            Box::new(SizeLimit {
+                reader: reader,
+                remaining: max_length as u64,
+                max_length: max_length,
+            })
+        );
+    }
+    Ok(reader)
+}
+
+/// "deflate" should be zlib wrapped but some clients send raw deflate
+fn deflate_reader<'a>(
+    mut reader: BufReader<Box<dyn Read + 'a>>
+) -> Result<Box<dyn Read + 'a>, HttpServerError> {
+    let header = reader.fill_buf().map_err(read_error)?;
+    let is_zlib =
+        header.len() >= 2 &&
+        (header[0] & 0x0f) == 8 &&
+        ((u16::from(header[0]) << 8) | u16::from(header[1])) % 31 == 0;
+    if is_zlib {
+        return Ok(Box::new(ZlibDecoder::new(reader)));
+    }
+    Ok(Box::new(DeflateDecoder::new(reader)))
+}
+
+/// Maps an error from reading the body to the response it should get.
+/// Timeouts are 408, decoded bodies over the limit are 413 and invalid compressed data is 400.
+pub fn read_error(err: io::Error) -> HttpServerError {
+    if is_timeout(&err) {
+        return HttpServerError::with_code(ResponseCode::RequestTimeout, "Timed out reading the body!");
+    }
+    if let Some(too_large) = err.get_ref().and_then(|inner| inner.downcast_ref::<TooLarge>()) {
+        return HttpServerError::with_code(ResponseCode::ContentTooLarge, &too_large.to_string());
+    }
+    // Reading the socket never fails with these, only the decoders do (truncated data is UnexpectedEof)
+    let is_invalid = matches!(
+        err.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof
+    );
+    if is_invalid {
+        return HttpServerError::with_code(
+            ResponseCode::BadRequest,
+            &format!("Invalid compressed body: {}", err)
+        );
+    }
+    HttpServerError::new(&format!("Error reading body: {}", err))
+}
+
+struct SizeLimit<R: Read> {
+    reader: R,
+    remaining: u64,
+    max_length: usize,
+}
+
+impl<R: Read> Read for SizeLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // One byte over the limit is allowed through so it can be detected
+        let max = buf.len().min((self.remaining as usize).saturating_add(1));
+        let size = self.reader.read(&mut buf[..max])?;
+        if (size as u64) > self.remaining {
+            return Err(io::Error::new(io::ErrorKind::Other, TooLarge(self.max_length)));
+        }
+        self.remaining -= size as u64;
+        Ok(size)
+    }
+}
+
+#[derive(Debug)]
+struct TooLarge(usize);
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decompressed body is over {} bytes!", self.0)
+    }
+}
+
+impl Error for TooLarge {}
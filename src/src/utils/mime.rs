@@ -0,0 +1,233 @@
+/// Extension to mime type table. Extensions are lowercase and without the dot.
+pub const MIME_TYPES: &[(&str, &str)] = &[
+    // Text
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),
+    ("vcf", "text/vcard"),
+    ("vtt", "text/vtt"),
+    ("xml", "text/xml"),
+    ("xaml", "text/xml"),
+    ("xsl", "text/xml"),
+    ("rtf", "application/rtf"),
+    // Scripts and data
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("cjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("atom", "application/atom+xml"),
+    ("rss", "application/rss+xml"),
+    // Images
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("jpe", "image/jpeg"),
+    ("jfif", "image/jpeg"),
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("svgz", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("cur", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("jxl", "image/jxl"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    ("weba", "audio/webm"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("ts", "video/mp2t"),
+    ("mkv", "video/mkv"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    // Documents
+    ("pdf", "application/pdf"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("epub", "application/epub+zip"),
+    // Archives and binaries
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("zst", "application/zstd"),
+    ("jar", "application/java-archive"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("exe", "application/octet-stream"),
+    ("dll", "application/octet-stream"),
+    ("bin", "application/octet-stream"),
+    ("iso", "application/octet-stream"),
+    ("dmg", "application/octet-stream"),
+    ("deb", "application/octet-stream"),
+];
+
+/// Extensions of files stored gzip compressed, they are sent with `Content-Encoding: gzip`.
+pub const GZIP_EXTENSIONS: &[&str] = &["svgz"];
+
+/// Looks up the mime type of an extension (case insensitive, without the dot).
+pub fn mime_from_extension(extension: &str) -> Option<&'static str> {
+    let extension = extension.to_lowercase();
+    MIME_TYPES.iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, mime)| *mime)
+}
+
+/// Guesses the mime type from the first bytes of a file (512 are enough).
+/// Returns None if the data is not recognised.
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"\x00asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"OTTO", "font/otf"),
+        (b"\x00\x01\x00\x00\x00", "font/ttf"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"MThd", "audio/midi"),
+    ];
+    for (signature, mime) in SIGNATURES {
+        if data.starts_with(signature) {
+            return Some(mime);
+        }
+    }
+    // Mp3 frame without id3 tag
+    if data.len() > 1 && data[0] == 0xff && (data[1] & 0xe0) == 0xe0 {
+        return Some("audio/mpeg");
+    }
+    // Riff containers
+    if data.len() >= 12 && data.starts_with(b"RIFF") {
+        return match &data[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    // Iso base media (mp4, avif, heic...)
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return match &data[8..12] {
+            b"avif" | b"avis" => Some("image/avif"),
+            b"heic" | b"heix" => Some("image/heic"),
+            b"qt  " => Some("video/quicktime"),
+            b"M4A " => Some("audio/mp4"),
+            _ => Some("video/mp4"),
+        };
+    }
+    // Matroska, webm declares its doctype in the header
+    if data.starts_with(b"\x1a\x45\xdf\xa3") {
+        if data.windows(4).any(|window| window == b"webm") {
+            return Some("video/webm");
+        }
+        return Some("video/mkv");
+    }
+
+    sniff_text(data)
+}
+
+fn sniff_text(data: &[u8]) -> Option<&'static str> {
+    let start = data
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let trimmed = &data[start..];
+    let starts_with_ignore_case = |prefix: &[u8]| {
+        trimmed.len() >= prefix.len() && trimmed[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+
+    if starts_with_ignore_case(b"<!doctype html") || starts_with_ignore_case(b"<html") {
+        return Some("text/html");
+    }
+    if starts_with_ignore_case(b"<svg") {
+        return Some("image/svg+xml");
+    }
+    if starts_with_ignore_case(b"<?xml") {
+        if data.windows(4).any(|window| window == b"<svg") {
+            return Some("image/svg+xml");
+        }
+        return Some("text/xml");
+    }
+    if data.is_empty() {
+        return None;
+    }
+    // Control characters (besides whitespace) mean binary data
+    let binary = data
+        .iter()
+        .any(|byte| *byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b));
+    if binary {
+        return None;
+    }
+    // The sample may cut a multi byte char at the end
+    match std::str::from_utf8(data) {
+        Ok(_) => Some("text/plain"),
+        Err(err) if err.error_len().is_none() => Some("text/plain"),
+        Err(_) => None,
+    }
+}
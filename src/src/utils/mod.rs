@@ -1,3 +1,8 @@
 pub mod utils;
 pub mod logger;
 
+pub mod mime;
+pub mod sendfile;
+pub mod deadline;
+pub mod decompress;
+pub mod http_date;
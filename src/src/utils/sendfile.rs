@@ -0,0 +1,82 @@
+use std::{ fs::File, io::{ self, Read, Seek, SeekFrom }, net::TcpStream };
+
+/// Copies `length` bytes of the file starting at `offset` into the stream.
+///
+/// On Linux this uses `sendfile` so the data never goes through userspace, other platforms
+/// (or filesystems that don't support it) fall back to a buffered copy.
+/// Returns the number of bytes sent.
+pub fn send_file(file: &File, stream: &TcpStream, offset: u64, length: u64) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        match linux::sendfile_all(file, stream, offset, length) {
+            Ok(sent) => {
+                return Ok(sent);
+            }
+            // Nothing was sent yet, so the fallback can start from the beginning
+            Err(err) if linux::is_unsupported(&err) => {}
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    copy_fallback(file, stream, offset, length)
+}
+
+fn copy_fallback(file: &File, stream: &TcpStream, offset: u64, length: u64) -> io::Result<u64> {
+    let mut file = file;
+    let mut stream = stream;
+    file.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut file.take(length), &mut stream)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{ fs::File, io, net::TcpStream, os::fd::AsRawFd };
+
+    extern "C" {
+        fn sendfile64(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize;
+    }
+
+    const EINVAL: i32 = 22;
+    const ENOSYS: i32 = 38;
+    // Linux never sends more than this in one call
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    pub fn is_unsupported(err: &io::Error) -> bool {
+        matches!(err.raw_os_error(), Some(EINVAL) | Some(ENOSYS))
+    }
+
+    pub fn sendfile_all(
+        file: &File,
+        stream: &TcpStream,
+        offset: u64,
+        length: u64
+    ) -> io::Result<u64> {
+        let mut offset = offset as i64;
+        let mut sent: u64 = 0;
+
+        while sent < length {
+            let count = (length - sent).min(MAX_CHUNK) as usize;
+            let result = unsafe {
+                sendfile64(stream.as_raw_fd(), file.as_raw_fd(), &mut offset, count)
+            };
+            if result < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if sent > 0 && is_unsupported(&err) {
+                    // Can't fall back in the middle of the body
+                    return Err(io::Error::new(io::ErrorKind::Other, err));
+                }
+                return Err(err);
+            }
+            if result == 0 {
+                // The file got shorter
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "File ended early"));
+            }
+            sent += result as u64;
+        }
+        Ok(sent)
+    }
+}
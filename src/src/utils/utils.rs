@@ -47,3 +47,71 @@ pub fn split_buffer_inxeses(buffer: &[u8], delimiter: &[u8]) -> Vec<(usize, usiz
 
     segments
 }
+/// The segments of a decoded url path, empty and "." segments are skipped.
+/// Returns None if a segment is "..", contains a '\' or a NUL byte.
+pub fn normalize_segments(path: &str) -> Option<Vec<&str>> {
+    let mut segments: Vec<&str> = Vec::new();
+
+    for segment in path.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if segment == ".." || segment.contains('\\') || segment.contains('\0') {
+            return None;
+        }
+        segments.push(segment);
+    }
+    Some(segments)
+}
+/// True if the path is the prefix or under it ("/api" matches "/api" and "/api/users", not "/apis").
+/// Empty segments are skipped like in route matching, so "//api" is under "/api" too.
+pub fn matches_prefix(prefix: &str, path: &str) -> bool {
+    let mut path = path.split('/').filter(|segment| !segment.is_empty());
+    prefix
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .all(|segment| path.next() == Some(segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches_prefix;
+    use crate::src::structs::Url;
+
+    /// The path the auth prefixes are matched against
+    fn request_path(target: &str) -> Option<String> {
+        Url::parse(&format!("GET {} HTTP/1.1", target)).ok().map(|url| url.path)
+    }
+
+    #[test]
+    fn prefix_matches_whole_segments() {
+        assert!(matches_prefix("/admin", "/admin"));
+        assert!(matches_prefix("/admin", "/admin/users"));
+        assert!(matches_prefix("/admin/", "/admin"));
+        assert!(matches_prefix("/", "/anything"));
+        assert!(!matches_prefix("/admin", "/administrator"));
+        assert!(!matches_prefix("/admin", "/"));
+    }
+
+    #[test]
+    fn prefix_skips_empty_segments() {
+        assert!(matches_prefix("/admin", "//admin"));
+        assert!(matches_prefix("/admin", "///admin"));
+        assert!(matches_prefix("/admin", "/admin//x"));
+        assert!(matches_prefix("/admin/x", "/admin//x"));
+        assert!(!matches_prefix("/admin", "//public/admin"));
+    }
+
+    #[test]
+    fn prefix_matches_the_normalized_request_path() {
+        let protected = |target: &str| {
+            request_path(target).map(|path| matches_prefix("/files/private", &path))
+        };
+        assert_eq!(protected("/files/private/secret.txt"), Some(true));
+        assert_eq!(protected("/files/./private/secret.txt"), Some(true));
+        assert_eq!(protected("/files/.%2Fprivate/secret.txt"), Some(true));
+        // Rejected with 400 before the auth check
+        assert_eq!(protected("/files/%5Cprivate/secret.txt"), None);
+        assert_eq!(protected("/files/public/../private/secret.txt"), None);
+    }
+}